@@ -0,0 +1,84 @@
+use crate::bitcask::{BitCask, KVStorage, Key, PutOption, Value};
+use crate::error::BitCaskError;
+use anyhow::anyhow;
+use std::path::PathBuf;
+
+/// An async handle to a [`BitCask`] for use from tokio.
+///
+/// Every call is run on tokio's blocking thread pool, so neither taking the lock nor the file IO ever blocks a runtime
+/// thread. Like [`BitCask`], the handle is cheap to clone and every clone refers to the same storage.
+#[derive(Clone)]
+pub struct AsyncBitCask {
+    inner: BitCask,
+}
+
+impl AsyncBitCask {
+    pub async fn new<T: Into<PathBuf>>(data_dir: T) -> Result<Self, BitCaskError> {
+        let data_dir: PathBuf = data_dir.into();
+        let inner = spawn_blocking(move || BitCask::new(data_dir)).await?;
+        Ok(Self { inner })
+    }
+
+    /// The blocking handle to the same storage.
+    pub fn blocking(&self) -> &BitCask {
+        &self.inner
+    }
+
+    pub async fn get(&self, key: Key) -> Result<Option<Value>, BitCaskError> {
+        let inner = self.inner.clone();
+        spawn_blocking(move || inner.try_get(&key)).await
+    }
+
+    pub async fn put(&self, key: Key, value: Value) -> Result<(), BitCaskError> {
+        self.put_with_option(key, value, PutOption::none()).await
+    }
+
+    pub async fn put_with_option(
+        &self,
+        key: Key,
+        value: Value,
+        option: Option<PutOption>,
+    ) -> Result<(), BitCaskError> {
+        let mut inner = self.inner.clone();
+        spawn_blocking(move || inner.put_with_option(&key, &value, option)).await
+    }
+
+    pub async fn delete(&self, key: Key) -> Result<(), BitCaskError> {
+        let mut inner = self.inner.clone();
+        spawn_blocking(move || inner.delete(&key)).await
+    }
+
+    pub async fn size(&self) -> Result<usize, BitCaskError> {
+        let inner = self.inner.clone();
+        spawn_blocking(move || Ok(inner.size())).await
+    }
+
+    /// See [`BitCask::compact_to_new_dir`].
+    pub async fn compact<T: Into<PathBuf>>(&self, data_dir: T) -> Result<(), BitCaskError> {
+        let inner = self.inner.clone();
+        let data_dir: PathBuf = data_dir.into();
+        spawn_blocking(move || inner.compact_to_new_dir(data_dir)).await
+    }
+
+    /// See [`BitCask::close`].
+    pub async fn close(&self) -> Result<(), BitCaskError> {
+        let inner = self.inner.clone();
+        spawn_blocking(move || inner.close()).await
+    }
+}
+
+impl From<BitCask> for AsyncBitCask {
+    fn from(inner: BitCask) -> Self {
+        Self { inner }
+    }
+}
+
+async fn spawn_blocking<F, R>(f: F) -> Result<R, BitCaskError>
+where
+    F: FnOnce() -> Result<R, BitCaskError> + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| anyhow!("blocking task failed: {}", e))?
+}
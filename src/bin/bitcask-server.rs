@@ -0,0 +1,63 @@
+use bitcask_engine_rs::bitcask::BitCask;
+use bitcask_engine_rs::memcached::MemcachedServer;
+#[cfg(feature = "prometheus")]
+use std::io::{Read, Write};
+
+#[cfg(not(feature = "prometheus"))]
+const USAGE: &str = "Usage: bitcask-server <data_dir> [listen_addr]";
+#[cfg(feature = "prometheus")]
+const USAGE: &str = "Usage: bitcask-server <data_dir> [listen_addr] [metrics_addr]";
+const DEFAULT_ADDR: &str = "127.0.0.1:11211";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let data_dir = match args.next() {
+        Some(data_dir) if data_dir != "-h" && data_dir != "--help" => data_dir,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let addr = args.next().unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let bitcask = BitCask::new(data_dir).unwrap_or_else(|e| {
+        eprintln!("failed to open bitcask: {}", e);
+        std::process::exit(1);
+    });
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics_addr) = args.next() {
+        serve_metrics(bitcask.clone(), &metrics_addr);
+    }
+    eprintln!("serving memcached protocol on {}", addr);
+    if let Err(e) = MemcachedServer::new(bitcask).bind(&addr) {
+        eprintln!("server error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Answer every HTTP request on `addr` with the statistics in the Prometheus text format, from a background thread.
+#[cfg(feature = "prometheus")]
+fn serve_metrics(bitcask: BitCask, addr: &str) {
+    let listener = std::net::TcpListener::bind(addr).unwrap_or_else(|e| {
+        eprintln!("failed to bind metrics address {}: {}", addr, e);
+        std::process::exit(1);
+    });
+    eprintln!("serving metrics on http://{}/metrics", addr);
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            // the request itself does not matter, every path serves the metrics
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let (status, body) = match bitcask.stats() {
+                Ok(stats) => ("200 OK", stats.to_prometheus()),
+                Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+}
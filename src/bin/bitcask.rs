@@ -0,0 +1,208 @@
+use bitcask_engine_rs::bitcask::{BitCask, KVStorage};
+use bitcask_engine_rs::error::BitCaskError;
+use bitcask_engine_rs::inspect::LogFileReader;
+use bitcask_engine_rs::repair::repair;
+use bitcask_engine_rs::verify::verify;
+
+const USAGE: &str = "Usage: bitcask [--format utf8|hex] <command>
+
+Commands:
+  --dir <data_dir> get <key>
+  --dir <data_dir> put <key> <value>
+  --dir <data_dir> delete <key>
+  --dir <data_dir> scan [--prefix <prefix>]
+  --dir <data_dir> stats
+  --dir <data_dir> compact <dest_dir>
+  dump <file>
+  verify <data_dir>
+  repair <src_dir> <dest_dir>
+
+Keys and values on the command line are parsed, and printed, in the given format (utf8 by default).
+`verify` prints a JSON report and exits with 1 if the data directory has problems.
+`repair` writes the records it can salvage, and the blob files, to <dest_dir> and prints a JSON report of the lost byte ranges.";
+
+/// How keys and values are parsed from the command line and rendered to stdout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Utf8,
+    Hex,
+}
+
+impl Format {
+    fn parse(&self, input: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Utf8 => Ok(input.as_bytes().to_vec()),
+            Format::Hex => {
+                let input = input.strip_prefix("0x").unwrap_or(input);
+                if !input.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(format!("invalid hex: {}", input));
+                }
+                if !input.len().is_multiple_of(2) {
+                    return Err(format!("odd number of hex digits: {}", input));
+                }
+                let digit = |b: &u8| (*b as char).to_digit(16).unwrap() as u8;
+                Ok(input
+                    .as_bytes()
+                    .chunks(2)
+                    .map(|pair| digit(&pair[0]) << 4 | digit(&pair[1]))
+                    .collect())
+            }
+        }
+    }
+
+    fn render(&self, bytes: &[u8]) -> String {
+        match self {
+            Format::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Format::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+struct Args {
+    format: Format,
+    data_dir: Option<String>,
+    command: Vec<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut format = Format::Utf8;
+    let mut data_dir = None;
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("utf8") => Format::Utf8,
+                    Some("hex") => Format::Hex,
+                    other => return Err(format!("unknown format: {:?}", other)),
+                }
+            }
+            "--hex" => format = Format::Hex,
+            "--dir" | "-d" => {
+                data_dir = Some(args.next().ok_or("--dir requires a value")?);
+            }
+            "--help" | "-h" => return Err(String::new()),
+            _ => command.push(arg),
+        }
+    }
+    Ok(Args {
+        format,
+        data_dir,
+        command,
+    })
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => exit_with_usage(&e),
+    };
+    if let Err(e) = run(args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), BitCaskError> {
+    let format = args.format;
+    let command: Vec<&str> = args.command.iter().map(String::as_str).collect();
+    match command.as_slice() {
+        ["dump", file] => return dump(file, format),
+        ["verify", data_dir] => {
+            let report = verify(data_dir)?;
+            println!("{}", report.to_json());
+            if !report.ok {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        ["repair", src_dir, dest_dir] => {
+            println!("{}", repair(src_dir, dest_dir)?.to_json());
+            return Ok(());
+        }
+        _ => {}
+    }
+    let data_dir = match &args.data_dir {
+        Some(data_dir) => data_dir,
+        None => exit_with_usage("--dir is required"),
+    };
+    let parse = |input: &str| format.parse(input).unwrap_or_else(|e| exit_with_usage(&e));
+    let mut bitcask = BitCask::new(data_dir)?;
+    match command.as_slice() {
+        ["get", key] => match bitcask.try_get(&parse(key))? {
+            Some(value) => {
+                println!("{}", format.render(&value));
+                Ok(())
+            }
+            None => {
+                eprintln!("key not found");
+                std::process::exit(1);
+            }
+        },
+        // the index snapshot written on close spares the next command the replay of what this one wrote
+        ["put", key, value] => {
+            bitcask.put(&parse(key), &parse(value))?;
+            bitcask.close()
+        }
+        ["delete", key] => {
+            bitcask.delete(&parse(key))?;
+            bitcask.close()
+        }
+        ["scan"] => scan(&bitcask, &[], format),
+        ["scan", "--prefix", prefix] => scan(&bitcask, &parse(prefix), format),
+        ["stats"] => {
+            let stats = bitcask.stats()?;
+            println!("files: {}", stats.file_count);
+            println!("live keys: {}", stats.live_keys);
+            println!("tombstones: {}", stats.tombstones);
+            println!("total bytes: {}", stats.total_bytes);
+            println!("dead bytes: {}", stats.dead_bytes);
+            println!("blob bytes: {}", stats.blob_bytes);
+            println!("index bytes per key: {:.1}", stats.index_bytes_per_key());
+            Ok(())
+        }
+        ["compact", dest] => {
+            bitcask.compact_to_new_dir(*dest)?;
+            bitcask.close()
+        }
+        _ => exit_with_usage(&format!("invalid command: {}", command.join(" "))),
+    }
+}
+
+fn scan(bitcask: &BitCask, prefix: &[u8], format: Format) -> Result<(), BitCaskError> {
+    for (key, value) in bitcask.scan_prefix(prefix)? {
+        println!("{}\t{}", format.render(&key), format.render(&value));
+    }
+    Ok(())
+}
+
+fn dump(file: &str, format: Format) -> Result<(), BitCaskError> {
+    println!("offset\tkey_size\tvalue_size\tchecksum\tflags\ttombstone\tkey\tvalue");
+    for record in LogFileReader::open(file)? {
+        let record = record?;
+        println!(
+            "{}\t{}\t{}\t{:08x}\t{:02x}\t{}\t{}\t{}",
+            record.offset,
+            record.key_size,
+            record.value_size,
+            record.check_sum,
+            record.flags,
+            record.tombstone,
+            format.render(&record.key),
+            record
+                .value
+                .map(|value| format.render(&value))
+                .unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    if !message.is_empty() {
+        eprintln!("error: {}\n", message);
+    }
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
@@ -1,7 +1,17 @@
+use crate::compression::Compression;
+#[cfg(feature = "encryption")]
+use crate::encryption::EncryptionKey;
 use crate::error::BitCaskError;
-use crate::storage::{start_compaction, LogIndexStorage};
+use crate::events::{Event, EventDelivery, EventListener, Events};
+use crate::export::{read_records, ExportFormat};
+use crate::group_commit::{GroupCommit, WriteOp};
+use crate::storage::{put_from_reader, read_lock, start_compaction, write_lock, LogIndexStorage};
+use std::io::{Read, Seek, Write};
+use std::ops::Bound;
 use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use tracing::error;
 
 pub(crate) type FileId = usize;
 pub(crate) type ByteSize = u64;
@@ -9,14 +19,30 @@ pub(crate) type ByteOffset = u64;
 pub type Key = Vec<u8>;
 pub type Value = Vec<u8>;
 
+const IMPORT_BATCH_SIZE: usize = 4096;
+
 pub trait KVStorage: Clone + Send + 'static {
-    fn get(&self, key: &Key) -> Option<Value>;
-    fn put_with_option(&mut self, key: &Key, value: &Value, option: Option<PutOption>) -> Result<(), BitCaskError>;
-    fn put(&mut self, key: &Key, value: &Value) -> Result<(), BitCaskError> {
+    /// Like [`KVStorage::try_get`], but read errors are logged and reported as a missing key.
+    fn get(&self, key: &[u8]) -> Option<Value> {
+        self.try_get(key).unwrap_or_else(|e| {
+            error!("Error while getting value: {:?}", e);
+            None
+        })
+    }
+    fn try_get(&self, key: &[u8]) -> Result<Option<Value>, BitCaskError>;
+    fn put_with_option(&mut self, key: &[u8], value: &[u8], option: Option<PutOption>) -> Result<(), BitCaskError>;
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), BitCaskError> {
         self.put_with_option(key, value, PutOption::none())
     }
-    fn delete(&mut self, key: &Key) -> Result<(), BitCaskError>;
+    fn delete(&mut self, key: &[u8]) -> Result<(), BitCaskError>;
     fn size(&self) -> usize;
+    /// Return all the key-value pairs whose key is within `range`, in key order. A range that starts after its end is
+    /// empty. Storages that do not keep their keys in order do not support it.
+    fn range(&self, _range: (Bound<Key>, Bound<Key>)) -> Result<Vec<(Key, Value)>, BitCaskError> {
+        Err(BitCaskError::UnexpectedError(anyhow::anyhow!(
+            "range scans are not supported by this storage"
+        )))
+    }
 }
 
 pub struct PutOption {
@@ -45,46 +71,418 @@ impl PutOption {
     }
 }
 
+/// The kind of in-memory index that maps keys to their values on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexType {
+    /// Keys are kept sorted, which suits range and prefix scans.
+    #[default]
+    BTree,
+    /// Keys are hashed, which makes point lookups faster but scans, exports and compaction sort the keys first.
+    Hash,
+    /// Keys are prefix-compressed and entries packed, for keyspaces that would not fit in memory otherwise. Lookups
+    /// and writes are slower than with `BTree`.
+    Compact,
+}
+
+/// Options for opening a [`BitCask`].
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Verify the checksum of every value that is read, and fail the read with `CorruptedData` on a mismatch.
+    /// Otherwise checksums are only verified when the log files are loaded at open time.
+    pub verify_checksums: bool,
+    /// fsync the log after every write, so that a write is durable once it returns.
+    pub sync: bool,
+    /// Queue concurrent writes and append them to the log in groups, with a single write and a single fsync per
+    /// group. This trades some latency of a lone writer for throughput under many writer threads.
+    pub group_commit: bool,
+    /// The in-memory index to use.
+    pub index: IndexType,
+    /// How values are compressed when they are written, including when compaction rewrites them.
+    pub compression: Compression,
+    /// Values shorter than this many bytes are not compressed.
+    pub compression_threshold: usize,
+    /// Store values of at least this many bytes in separate blob files, and only a pointer to them in the log, so that
+    /// compaction does not rewrite them. Blob files are reclaimed by [`BitCask::gc_blobs`] instead. Blobs are moved
+    /// as they are stored, so they keep the compression and the encryption key they were written with.
+    pub blob_threshold: Option<usize>,
+    /// Keep recently read values in memory, up to this many bytes of keys and values. Only point lookups go through
+    /// the cache.
+    pub cache_size: Option<usize>,
+    /// Encrypt values with this key. Keys are stored in clear, so that data directories can still be verified and
+    /// repaired without the key.
+    #[cfg(feature = "encryption")]
+    pub encryption_key: Option<EncryptionKey>,
+    /// Keys that older values may be encrypted with. They are only used to decrypt, so opening with a new
+    /// `encryption_key` and the previous one here, then compacting, rotates the key.
+    #[cfg(feature = "encryption")]
+    pub old_encryption_keys: Vec<EncryptionKey>,
+    /// Called when the engine does something notable, see [`Event`].
+    pub event_listeners: Vec<Arc<dyn EventListener>>,
+    /// How the listeners are called. `Sync` calls them inline, so they see the events in order but block the operation
+    /// that caused them. `Background` queues the events for a single thread, which calls them in the same order
+    /// without blocking the engine, after the operation may have returned.
+    pub event_delivery: EventDelivery,
+    /// Lock the data directory while it is open, so that opening it again, from this process or another one, fails
+    /// with `WouldBlock`. The lock follows the data directory to the directory of a compaction.
+    pub lock_directory: bool,
+    /// At open, cut off the last record of the log if the process stopped in the middle of appending it, and report it
+    /// as [`Event::RecoveryTruncated`]. Only a record that runs to the end of the file is cut, any other damage still
+    /// fails the open with `CorruptedData`. Off by default, where a torn record fails the open too, and is left for
+    /// [`crate::repair::repair`].
+    pub truncate_torn_tail: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            verify_checksums: false,
+            sync: false,
+            group_commit: false,
+            index: IndexType::BTree,
+            compression: Compression::None,
+            compression_threshold: 64,
+            blob_threshold: None,
+            cache_size: None,
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+            #[cfg(feature = "encryption")]
+            old_encryption_keys: Vec::new(),
+            event_listeners: Vec::new(),
+            event_delivery: EventDelivery::Sync,
+            lock_directory: false,
+            truncate_torn_tail: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub fn group_commit(mut self, group_commit: bool) -> Self {
+        self.group_commit = group_commit;
+        self
+    }
+
+    pub fn index(mut self, index: IndexType) -> Self {
+        self.index = index;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn compression_threshold(mut self, compression_threshold: usize) -> Self {
+        self.compression_threshold = compression_threshold;
+        self
+    }
+
+    pub fn blob_threshold(mut self, blob_threshold: usize) -> Self {
+        self.blob_threshold = Some(blob_threshold);
+        self
+    }
+
+    pub fn cache_size(mut self, cache_size: usize) -> Self {
+        self.cache_size = Some(cache_size);
+        self
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, encryption_key: EncryptionKey) -> Self {
+        self.encryption_key = Some(encryption_key);
+        self
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn old_encryption_keys(mut self, old_encryption_keys: Vec<EncryptionKey>) -> Self {
+        self.old_encryption_keys = old_encryption_keys;
+        self
+    }
+
+    /// Add a listener of the events of the engine. Listeners are called in the order they were added.
+    pub fn event_listener(mut self, event_listener: Arc<dyn EventListener>) -> Self {
+        self.event_listeners.push(event_listener);
+        self
+    }
+
+    pub fn event_delivery(mut self, event_delivery: EventDelivery) -> Self {
+        self.event_delivery = event_delivery;
+        self
+    }
+
+    pub fn lock_directory(mut self, lock_directory: bool) -> Self {
+        self.lock_directory = lock_directory;
+        self
+    }
+
+    pub fn truncate_torn_tail(mut self, truncate_torn_tail: bool) -> Self {
+        self.truncate_torn_tail = truncate_torn_tail;
+        self
+    }
+}
+
+/// A snapshot of the state of the data directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of log files.
+    pub file_count: usize,
+    /// The id and the size in bytes of every log file, in file id order.
+    pub file_sizes: Vec<(usize, u64)>,
+    /// Number of keys that have a value.
+    pub live_keys: usize,
+    /// Number of deleted keys whose tombstones are still in the log, until compaction drops them.
+    pub tombstones: usize,
+    /// Size of all log files in bytes.
+    pub total_bytes: u64,
+    /// Bytes taken by overwritten values and tombstones, which are reclaimed by compaction.
+    pub dead_bytes: u64,
+    /// Approximate memory taken by the in-memory index.
+    pub index_bytes: u64,
+    /// Size of all blob files in bytes.
+    pub blob_bytes: u64,
+    /// Number of point lookups served from the read cache.
+    pub cache_hits: u64,
+    /// Number of point lookups of existing keys that had to read the disk, always 0 without a read cache.
+    pub cache_misses: u64,
+    /// Bytes appended to log and blob files since the data directory was opened.
+    pub bytes_written: u64,
+    /// Bytes read from log and blob files since the data directory was opened, not counting streamed values.
+    pub bytes_read: u64,
+    /// Point lookups, including the keys of `multi_get`.
+    pub gets: OpStats,
+    /// Puts, including the pairs of batches. A batch adds its time once, and each pair counts for an even share of it.
+    pub puts: OpStats,
+    pub deletes: OpStats,
+    /// Range and prefix scans.
+    pub scans: OpStats,
+}
+
+/// How many operations of a kind were done since the data directory was opened, and how long they took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpStats {
+    pub count: u64,
+    pub total_time: Duration,
+    pub max_time: Duration,
+}
+
+impl OpStats {
+    pub fn mean_time(&self) -> Duration {
+        Duration::from_nanos((self.total_time.as_nanos() / u128::from(self.count.max(1))) as u64)
+    }
+}
+
+impl Stats {
+    /// Approximate memory taken by the in-memory index per live key.
+    pub fn index_bytes_per_key(&self) -> f64 {
+        self.index_bytes as f64 / self.live_keys.max(1) as f64
+    }
+}
+
 #[derive(Clone)]
 pub struct BitCask {
     pub(crate) storage: Arc<RwLock<LogIndexStorage>>,
+    group_commit: Option<Arc<GroupCommit>>,
+    events: Arc<Events>,
 }
 
 impl BitCask {
     pub fn new<T: Into<PathBuf>>(data_dir: T) -> Result<Self, BitCaskError> {
-        let storage = LogIndexStorage::new(data_dir)?;
+        Self::with_options(data_dir, Options::default())
+    }
+
+    pub fn with_options<T: Into<PathBuf>>(data_dir: T, options: Options) -> Result<Self, BitCaskError> {
+        let group_commit = options.group_commit.then(|| Arc::new(GroupCommit::default()));
+        let storage = LogIndexStorage::new(data_dir, options)?;
+        let events = storage.events();
         Ok(Self {
             storage: Arc::new(RwLock::new(storage)),
+            group_commit,
+            events,
         })
     }
 
+    /// Close the storage for every handle: stop the event thread, fsync the active log file, write the index snapshot
+    /// that the next open loads instead of replaying the log, and release the directory lock. Every operation that can
+    /// fail fails with `Closed` from then on, including another `close`. A storage that is dropped without being
+    /// closed only gets its active log file fsynced.
+    pub fn close(&self) -> Result<(), BitCaskError> {
+        self.write_storage()?.close()?;
+        // once the lock is released, so that a background listener using the storage can finish
+        self.events.stop();
+        Ok(())
+    }
+
     /// WARNING: this method is a blocking call, it will block the current thread until the compaction is finished.
     /// If you're using this method in an async context, you should spawn a blocking worker thread to call this method.
     pub fn compact_to_new_dir<T: Into<PathBuf>>(&self, data_dir: T) -> Result<(), BitCaskError> {
-        let mut storage = self.storage.write().unwrap();
+        let mut storage = self.write_storage()?;
         let data_dir: PathBuf = data_dir.into();
         let immutable_files = storage.prepare_compaction()?;
+        let options = storage.options().clone();
         drop(storage);
-        start_compaction(immutable_files.clone(), data_dir.clone())?;
-        let mut storage = self.storage.write().unwrap();
+        start_compaction(immutable_files.clone(), data_dir.clone(), &options)?;
+        let mut storage = self.write_storage()?;
         storage.finish_compaction(immutable_files, data_dir)
     }
+
+    /// Get the values of all the keys under a single acquisition of the lock. Read errors are logged, passed to the
+    /// event listeners as [`Event::BackgroundError`] and reported as missing keys, like [`KVStorage::get`].
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Vec<Option<Value>> {
+        self.try_multi_get(keys).unwrap_or_else(|e| {
+            error!("Error while getting values: {:?}", e);
+            self.events.emit(Event::BackgroundError {
+                message: e.to_string(),
+            });
+            vec![None; keys.len()]
+        })
+    }
+
+    /// Get the values of all the keys under a single acquisition of the lock, reading them in the order they are laid
+    /// out on disk. Fails as a whole if any read fails.
+    pub fn try_multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>, BitCaskError> {
+        self.read_storage()?.multi_get(keys)
+    }
+
+    /// Put all the key-value pairs with a single write. If a key appears more than once, the last pair wins.
+    pub fn multi_put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, pairs: &[(K, V)]) -> Result<(), BitCaskError> {
+        self.write_storage()?.put_batch(pairs)
+    }
+
+    /// Return a reader of the value of `key`, which reads the value from disk as it goes instead of all at once. Values
+    /// that are compressed or encrypted are decoded in memory first. With `verify_checksums`, reading the value in
+    /// order up to its end fails with `InvalidData` if it does not match its checksum.
+    pub fn get_reader(&self, key: &[u8]) -> Result<Option<impl Read + Seek>, BitCaskError> {
+        self.read_storage()?.get_reader(key)
+    }
+
+    /// Put a value of `len` bytes read from `reader`, without holding it in memory at once unless it has to be
+    /// compressed or encrypted. Nothing is written if the reader fails or ends early.
+    /// The value is streamed to a staging file in the data directory first, and the storage is only locked to put it
+    /// from there: a value above the blob threshold becomes a blob file without being copied again.
+    pub fn put_from_reader<R: Read>(&mut self, key: &[u8], reader: R, len: u64) -> Result<(), BitCaskError> {
+        put_from_reader(&self.storage, key, reader, len)
+    }
+
+    /// Like [`KVStorage::get`], but returns the value as [`bytes::Bytes`], which takes over the read buffer without
+    /// copying it.
+    #[cfg(feature = "bytes")]
+    pub fn get_bytes(&self, key: &[u8]) -> Option<bytes::Bytes> {
+        self.get(key).map(bytes::Bytes::from)
+    }
+
+    /// Return all the key-value pairs whose key starts with `prefix`, in key order.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Key, Value)>, BitCaskError> {
+        self.read_storage()?.scan_prefix(prefix)
+    }
+
+    /// Stream all the live key-value pairs to `writer` in key order, and return how many were written.
+    /// Writers are blocked until the export is finished.
+    pub fn export<W: Write>(&self, writer: W, format: ExportFormat) -> Result<usize, BitCaskError> {
+        self.read_storage()?.export(writer, format)
+    }
+
+    /// Bulk-load a dump written by [`BitCask::export`], and return how many key-value pairs were imported.
+    /// Pairs are appended in batches, each with a single write and a single acquisition of the lock.
+    pub fn import<R: Read>(&self, reader: R, format: ExportFormat) -> Result<usize, BitCaskError> {
+        let mut records = read_records(reader, format);
+        let mut count = 0;
+        loop {
+            let batch = records
+                .by_ref()
+                .take(IMPORT_BATCH_SIZE)
+                .collect::<Result<Vec<(Key, Value)>, BitCaskError>>()?;
+            if batch.is_empty() {
+                return Ok(count);
+            }
+            count += batch.len();
+            self.write_storage()?.put_batch(&batch)?;
+        }
+    }
+
+    /// Number of deleted keys whose tombstones are still in the log, until compaction drops them.
+    pub fn tombstone_count(&self) -> usize {
+        self.storage.read().unwrap_or_else(PoisonError::into_inner).tombstone_count()
+    }
+
+    pub fn stats(&self) -> Result<Stats, BitCaskError> {
+        self.read_storage()?.stats()
+    }
+
+    /// Reclaim the blob files of which at least `min_garbage_ratio` of the bytes belong to overwritten or deleted
+    /// values: their live values are moved to the current blob file, then they are deleted. Returns the number of
+    /// bytes reclaimed.
+    ///
+    /// WARNING: this method is a blocking call, and holds the lock while the live values are copied.
+    pub fn gc_blobs(&self, min_garbage_ratio: f64) -> Result<u64, BitCaskError> {
+        self.write_storage()?.gc_blobs(min_garbage_ratio)
+    }
+
+    /// Rebuild the in-memory state of the storage from the log, which is what a panic while holding the storage lock
+    /// leaves unknown. Until then, every operation that can fail fails with `Poisoned`. A record that the panic left
+    /// half written at the end of the log is cut off, even without `Options::truncate_torn_tail`. It can also be called
+    /// at any time to revalidate the storage against the log.
+    ///
+    /// WARNING: this method is a blocking call, it replays the log like opening the data directory does.
+    pub fn recover(&self) -> Result<(), BitCaskError> {
+        let mut storage = self.storage.write().unwrap_or_else(PoisonError::into_inner);
+        storage.recover()?;
+        self.storage.clear_poison();
+        Ok(())
+    }
+
+    fn read_storage(&self) -> Result<RwLockReadGuard<'_, LogIndexStorage>, BitCaskError> {
+        read_lock(&self.storage)
+    }
+
+    fn write_storage(&self) -> Result<RwLockWriteGuard<'_, LogIndexStorage>, BitCaskError> {
+        write_lock(&self.storage)
+    }
 }
 
 impl KVStorage for BitCask {
-    fn get(&self, key: &Key) -> Option<Value> {
-        self.storage.read().unwrap().get(key)
+    fn get(&self, key: &[u8]) -> Option<Value> {
+        self.try_get(key).unwrap_or_else(|e| {
+            error!("Error while getting value: {:?}", e);
+            self.events.emit(Event::BackgroundError {
+                message: e.to_string(),
+            });
+            None
+        })
+    }
+
+    fn try_get(&self, key: &[u8]) -> Result<Option<Value>, BitCaskError> {
+        self.read_storage()?.get(key)
     }
 
-    fn put_with_option(&mut self, key: &Key, value: &Value, option: Option<PutOption>) -> Result<(), BitCaskError> {
-        self.storage.write().unwrap().put(key, value, option)
+    fn put_with_option(&mut self, key: &[u8], value: &[u8], option: Option<PutOption>) -> Result<(), BitCaskError> {
+        match &self.group_commit {
+            Some(group_commit) => group_commit.submit(&self.storage, WriteOp::put(key, value, option)),
+            None => self.write_storage()?.put(key, value, option),
+        }
     }
 
-    fn delete(&mut self, key: &Key) -> Result<(), BitCaskError> {
-        self.storage.write().unwrap().delete(key)
+    fn delete(&mut self, key: &[u8]) -> Result<(), BitCaskError> {
+        match &self.group_commit {
+            Some(group_commit) => group_commit.submit(&self.storage, WriteOp::delete(key)),
+            None => self.write_storage()?.delete(key),
+        }
     }
 
     fn size(&self) -> usize {
-        self.storage.read().unwrap().size()
+        // a count is still meaningful after a panic, if maybe off by the write that panicked
+        self.storage.read().unwrap_or_else(PoisonError::into_inner).size()
+    }
+
+    fn range(&self, range: (Bound<Key>, Bound<Key>)) -> Result<Vec<(Key, Value)>, BitCaskError> {
+        self.read_storage()?.range(range)
     }
 }
@@ -0,0 +1,206 @@
+use crate::bitcask::{ByteOffset, ByteSize, FileId};
+use crate::error::BitCaskError;
+use crate::log_entry::checksum;
+use crate::stream::{copy_with_checksum, read_exact_at, StagingFile};
+use std::ffi::OsStr;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::trace;
+
+/// A file that large values are appended to, apart from the log, so that compaction does not have to rewrite them.
+/// It holds the stored bytes of values back to back, and the log records of these values point into it.
+pub(crate) struct BlobFile {
+    pub(crate) file_id: FileId,
+    pub(crate) path: PathBuf,
+    pub(crate) file: std::fs::File,
+}
+
+impl BlobFile {
+    pub(crate) const EXT: &'static str = "blob";
+    pub(crate) const MAX_FILE_SIZE: u64 = 1024 * 1024 * 1024; // 1GB
+
+    /// Create a new blob file for appending.
+    pub(crate) fn new(data_dir: &Path, file_id: FileId) -> Result<Self, BitCaskError> {
+        let path = Self::path(data_dir, file_id);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        Ok(Self {
+            file_id,
+            path,
+            file,
+        })
+    }
+
+    /// Turn `staging_file` into a new blob file that holds its value alone, and return the pointer to the value.
+    pub(crate) fn from_staging_file(
+        data_dir: &Path,
+        file_id: FileId,
+        staging_file: &mut StagingFile,
+    ) -> Result<(Self, BlobPointer), BitCaskError> {
+        let path = Self::path(data_dir, file_id);
+        staging_file.persist(&path)?;
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        let pointer = BlobPointer {
+            file_id,
+            offset: 0,
+            size: staging_file.len,
+            check_sum: staging_file.check_sum,
+        };
+        Ok((
+            Self {
+                file_id,
+                path,
+                file,
+            },
+            pointer,
+        ))
+    }
+
+    fn path(data_dir: &Path, file_id: FileId) -> PathBuf {
+        let mut path = data_dir.to_path_buf();
+        path.push(file_id.to_string());
+        path.set_extension(Self::EXT);
+        path
+    }
+
+    /// Open all the blob files of `data_dir`, in file id order.
+    pub(crate) fn open_all(data_dir: &Path) -> Result<Vec<Self>, BitCaskError> {
+        let mut files: Vec<(FileId, PathBuf)> = std::fs::read_dir(data_dir)?
+            .filter_map(|path| {
+                path.ok().map(|path| path.path()).filter(|path| {
+                    path.is_file() && path.extension() == Some(OsStr::new(Self::EXT))
+                })
+            })
+            .filter_map(|path| {
+                path.file_stem()
+                    .and_then(|file_stem| file_stem.to_str())
+                    .and_then(|file_stem| file_stem.parse::<FileId>().ok())
+                    .map(|file_id| (file_id, path))
+            })
+            .collect();
+        files.sort_by_key(|(file_id, _)| *file_id);
+        files
+            .into_iter()
+            .map(|(file_id, path)| {
+                trace!("opening blob file: {:?}", path);
+                let file = std::fs::OpenOptions::new()
+                    .read(true)
+                    .append(true)
+                    .open(&path)?;
+                Ok(Self {
+                    file_id,
+                    path,
+                    file,
+                })
+            })
+            .collect()
+    }
+
+    /// Append `value` and return where it is stored.
+    pub(crate) fn append(&mut self, value: &[u8]) -> Result<BlobPointer, BitCaskError> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(value)?;
+        self.file.flush()?;
+        Ok(BlobPointer {
+            file_id: self.file_id,
+            offset,
+            size: value.len() as u64,
+            check_sum: checksum(value),
+        })
+    }
+
+    /// Append a value of `len` bytes streamed from `reader`, and return where it is stored. If the reader fails or ends
+    /// early, the partial value is cut off.
+    pub(crate) fn append_from_reader<R: Read>(&mut self, reader: R, len: u64) -> Result<BlobPointer, BitCaskError> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let mut writer = BufWriter::new(&self.file);
+        let result = copy_with_checksum(reader, &mut writer, len).and_then(|check_sum| {
+            writer.flush()?;
+            Ok(check_sum)
+        });
+        drop(writer);
+        match result {
+            Ok(check_sum) => Ok(BlobPointer {
+                file_id: self.file_id,
+                offset,
+                size: len,
+                check_sum,
+            }),
+            Err(e) => {
+                self.file.set_len(offset)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Read the value `pointer` points to, which must lie within the file.
+    pub(crate) fn read(&self, pointer: &BlobPointer) -> Result<Vec<u8>, BitCaskError> {
+        let file_size = self.size()?;
+        match pointer.offset.checked_add(pointer.size) {
+            Some(end) if end <= file_size => {}
+            _ => {
+                return Err(BitCaskError::CorruptedData(format!(
+                    "blob of {} bytes at offset {} is past the end of blob file {} ({} bytes)",
+                    pointer.size, pointer.offset, self.file_id, file_size
+                )))
+            }
+        }
+        let mut buf = vec![0u8; pointer.size as usize];
+        read_exact_at(&self.file, &mut buf, pointer.offset)?;
+        Ok(buf)
+    }
+
+    pub(crate) fn size(&self) -> Result<u64, BitCaskError> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+/// Where a value is stored in a blob file. It is the value of the log record of the key, which has the blob flag.
+///
+/// Disk layout
+///  - File id of the blob file (8 bytes long)
+///  - Offset of the value in the blob file (8 bytes long)
+///  - Size of the value in bytes (8 bytes long)
+///  - Checksum of the value (4 bytes long)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlobPointer {
+    pub(crate) file_id: FileId,
+    pub(crate) offset: ByteOffset,
+    pub(crate) size: ByteSize,
+    pub(crate) check_sum: u32,
+}
+
+impl BlobPointer {
+    pub(crate) const BYTE_SIZE: usize = 28;
+
+    pub(crate) fn encode(&self) -> [u8; Self::BYTE_SIZE] {
+        let mut buf = [0u8; Self::BYTE_SIZE];
+        buf[..8].copy_from_slice(&(self.file_id as u64).to_be_bytes());
+        buf[8..16].copy_from_slice(&self.offset.to_be_bytes());
+        buf[16..24].copy_from_slice(&self.size.to_be_bytes());
+        buf[24..].copy_from_slice(&self.check_sum.to_be_bytes());
+        buf
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self, BitCaskError> {
+        if buf.len() != Self::BYTE_SIZE {
+            return Err(BitCaskError::CorruptedData(format!(
+                "invalid blob pointer of {} bytes",
+                buf.len()
+            )));
+        }
+        let u64_at = |at: usize| u64::from_be_bytes(buf[at..at + 8].try_into().unwrap());
+        Ok(Self {
+            file_id: u64_at(0) as FileId,
+            offset: u64_at(8),
+            size: u64_at(16),
+            check_sum: u32::from_be_bytes(buf[24..].try_into().unwrap()),
+        })
+    }
+}
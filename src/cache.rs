@@ -0,0 +1,78 @@
+use crate::bitcask::{Key, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// A cache of recently read values, bounded by the total size of their keys and values. The least recently used
+/// values are evicted first.
+pub(crate) struct ValueCache {
+    capacity: usize,
+    size: usize,
+    /// Every cached value, with the tick of its last use.
+    entries: HashMap<Key, (Value, u64)>,
+    /// The cached keys by the tick of their last use, oldest first.
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+}
+
+impl ValueCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: &[u8]) -> Option<Value> {
+        let (value, last_used) = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        self.hits += 1;
+        self.tick += 1;
+        let key = self.recency.remove(last_used).unwrap();
+        *last_used = self.tick;
+        let value = value.clone();
+        self.recency.insert(self.tick, key);
+        Some(value)
+    }
+
+    pub(crate) fn insert(&mut self, key: Key, value: Value) {
+        self.invalidate(&key);
+        let size = key.len() + value.len();
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            let (value, _) = self.entries.remove(&oldest).unwrap();
+            self.size -= oldest.len() + value.len();
+        }
+        self.tick += 1;
+        self.size += size;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub(crate) fn invalidate(&mut self, key: &[u8]) {
+        if let Some((value, last_used)) = self.entries.remove(key) {
+            self.recency.remove(&last_used);
+            self.size -= key.len() + value.len();
+        }
+    }
+
+    /// Drop every cached value, but keep the counters.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.size = 0;
+    }
+}
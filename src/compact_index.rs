@@ -0,0 +1,321 @@
+use crate::bitcask::{FileId, Key};
+use crate::memory_index::{Index, IndexIter, MemIndexEntry};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
+
+/// Number of keys per block of [`PackedKeys`]. The first key of a block is stored whole, so that lookups can binary
+/// search the blocks and only decode one of them.
+const BLOCK_LEN: usize = 16;
+/// The changes are merged into the packed keys once they reach this many keys, or an eighth of the packed keys,
+/// whichever is larger. Growing the threshold with the index keeps the total cost of merging linear.
+const MIN_MERGE_LEN: usize = 4096;
+
+/// An index for very large keyspaces, which trades some CPU for memory.
+///
+/// Most of the keys live in [`PackedKeys`], a sorted, prefix-compressed array in a single allocation, where an entry
+/// takes a dozen bytes on top of the part of its key it does not share with the previous key. Writes go to a small
+/// B-tree of changes first, which is merged into the packed keys as it grows.
+pub(crate) struct CompactIndex {
+    packed: PackedKeys,
+    /// `None` marks a key of `packed` that has been deleted since the last merge.
+    changes: BTreeMap<Key, Option<MemIndexEntry>>,
+    len: usize,
+}
+
+impl CompactIndex {
+    pub(crate) fn new() -> Self {
+        Self {
+            packed: PackedKeys::default(),
+            changes: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    fn merge_if_needed(&mut self) {
+        if self.changes.len() < MIN_MERGE_LEN.max(self.packed.len / 8) {
+            return;
+        }
+        let changes = std::mem::take(&mut self.changes);
+        let merged = Merge::new(
+            self.packed.iter_from(Bound::Unbounded),
+            changes.iter().map(|(key, entry)| (key, *entry)),
+        );
+        self.packed = PackedKeys::build(merged);
+    }
+}
+
+impl Index for CompactIndex {
+    fn get(&self, key: &[u8]) -> Option<MemIndexEntry> {
+        match self.changes.get(key) {
+            Some(entry) => *entry,
+            None => self.packed.get(key),
+        }
+    }
+    fn put(&mut self, key: Key, entry: MemIndexEntry) -> Option<MemIndexEntry> {
+        let previous = self.get(&key);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        self.changes.insert(key, Some(entry));
+        self.merge_if_needed();
+        previous
+    }
+    fn delete(&mut self, key: &[u8]) -> Option<MemIndexEntry> {
+        let previous = self.get(key)?;
+        self.len -= 1;
+        if self.packed.get(key).is_some() {
+            self.changes.insert(key.to_vec(), None);
+            self.merge_if_needed();
+        } else {
+            self.changes.remove(key);
+        }
+        Some(previous)
+    }
+    fn size(&self) -> usize {
+        self.len
+    }
+    fn iter(&self) -> IndexIter<'_> {
+        self.range((Bound::Unbounded, Bound::Unbounded))
+    }
+    fn range(&self, range: (Bound<Key>, Bound<Key>)) -> IndexIter<'_> {
+        let packed = self
+            .packed
+            .iter_from(range.start_bound().map(|key| key.as_slice()));
+        let changes = self
+            .changes
+            .range(range.clone())
+            .map(|(key, entry)| (key, *entry));
+        let end = range.1;
+        Box::new(
+            Merge::new(packed, changes).take_while(move |(key, _)| match &end {
+                Bound::Included(end) => key.as_ref() <= end.as_slice(),
+                Bound::Excluded(end) => key.as_ref() < end.as_slice(),
+                Bound::Unbounded => true,
+            }),
+        )
+    }
+    fn heap_bytes(&self) -> usize {
+        let change_bytes = size_of::<Key>() + size_of::<Option<MemIndexEntry>>();
+        self.packed.data.capacity()
+            + self.packed.block_offsets.capacity() * size_of::<usize>()
+            + self.changes.keys().map(|key| key.capacity()).sum::<usize>()
+            + self.changes.len() * change_bytes * 3 / 2
+    }
+}
+
+/// Merges the sorted keys of the packed array with the sorted changes, dropping the deleted keys.
+struct Merge<'a, P: Iterator, C: Iterator> {
+    packed: Peekable<P>,
+    changes: Peekable<C>,
+    _marker: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a, P, C> Merge<'a, P, C>
+where
+    P: Iterator<Item = (Key, MemIndexEntry)>,
+    C: Iterator<Item = (&'a Key, Option<MemIndexEntry>)>,
+{
+    fn new(packed: P, changes: C) -> Self {
+        Self {
+            packed: packed.peekable(),
+            changes: changes.peekable(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, P, C> Iterator for Merge<'a, P, C>
+where
+    P: Iterator<Item = (Key, MemIndexEntry)>,
+    C: Iterator<Item = (&'a Key, Option<MemIndexEntry>)>,
+{
+    type Item = (Cow<'a, [u8]>, MemIndexEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.packed.peek(), self.changes.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((packed_key, _)), Some((changed_key, _))) => packed_key.cmp(changed_key),
+            };
+            if order == Ordering::Less {
+                let (key, entry) = self.packed.next().unwrap();
+                return Some((Cow::Owned(key), entry));
+            }
+            if order == Ordering::Equal {
+                self.packed.next();
+            }
+            let (key, entry) = self.changes.next().unwrap();
+            if let Some(entry) = entry {
+                return Some((Cow::Borrowed(key), entry));
+            }
+        }
+    }
+}
+
+/// Sorted keys and their entries, packed in blocks of [`BLOCK_LEN`]. Each key is stored as the length of the prefix
+/// it shares with the previous key of the block and the rest of the key, and each entry as varints plus the checksum
+/// and the flags.
+#[derive(Default)]
+struct PackedKeys {
+    data: Vec<u8>,
+    block_offsets: Vec<usize>,
+    len: usize,
+}
+
+impl PackedKeys {
+    fn build<'a>(entries: impl Iterator<Item = (Cow<'a, [u8]>, MemIndexEntry)>) -> Self {
+        let mut packed = Self::default();
+        let mut previous_key: Vec<u8> = Vec::new();
+        for (key, entry) in entries {
+            let shared = if packed.len % BLOCK_LEN == 0 {
+                packed.block_offsets.push(packed.data.len());
+                0
+            } else {
+                previous_key
+                    .iter()
+                    .zip(key.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            };
+            write_varint(&mut packed.data, shared as u64);
+            write_varint(&mut packed.data, (key.len() - shared) as u64);
+            packed.data.extend_from_slice(&key[shared..]);
+            write_varint(&mut packed.data, entry.file_id as u64);
+            write_varint(&mut packed.data, entry.value_offset);
+            write_varint(&mut packed.data, entry.value_size);
+            packed
+                .data
+                .extend_from_slice(&entry.check_sum.to_be_bytes());
+            packed.data.push(entry.flags);
+            previous_key.clear();
+            previous_key.extend_from_slice(&key);
+            packed.len += 1;
+        }
+        packed.data.shrink_to_fit();
+        packed.block_offsets.shrink_to_fit();
+        packed
+    }
+
+    /// The first key of a block, which is stored whole.
+    fn first_key(&self, block: usize) -> &[u8] {
+        let mut cursor = self.block_offsets[block];
+        read_varint(&self.data, &mut cursor);
+        let key_len = read_varint(&self.data, &mut cursor) as usize;
+        &self.data[cursor..cursor + key_len]
+    }
+
+    /// The block that `key` belongs in, i.e. the last block whose first key is not greater than `key`.
+    fn block_of(&self, key: &[u8]) -> Option<usize> {
+        let (mut low, mut high) = (0, self.block_offsets.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.first_key(middle) <= key {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low.checked_sub(1)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<MemIndexEntry> {
+        let block = self.block_of(key)?;
+        self.iter_block(block)
+            .take(BLOCK_LEN)
+            .find(|(packed_key, _)| packed_key.as_slice() >= key)
+            .filter(|(packed_key, _)| packed_key.as_slice() == key)
+            .map(|(_, entry)| entry)
+    }
+
+    fn iter_block(&self, block: usize) -> PackedIter<'_> {
+        PackedIter {
+            packed: self,
+            cursor: self
+                .block_offsets
+                .get(block)
+                .copied()
+                .unwrap_or(self.data.len()),
+            key: Vec::new(),
+        }
+    }
+
+    /// Iterate over the keys from `start` on.
+    fn iter_from(&self, start: Bound<&[u8]>) -> impl Iterator<Item = (Key, MemIndexEntry)> + '_ {
+        let block = match start {
+            Bound::Included(key) | Bound::Excluded(key) => self.block_of(key).unwrap_or(0),
+            Bound::Unbounded => 0,
+        };
+        let start: Bound<Key> = start.map(|key| key.to_vec());
+        self.iter_block(block)
+            .skip_while(move |(key, _)| match &start {
+                Bound::Included(start) => key < start,
+                Bound::Excluded(start) => key <= start,
+                Bound::Unbounded => false,
+            })
+    }
+}
+
+struct PackedIter<'a> {
+    packed: &'a PackedKeys,
+    cursor: usize,
+    key: Vec<u8>,
+}
+
+impl Iterator for PackedIter<'_> {
+    type Item = (Key, MemIndexEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = &self.packed.data;
+        if self.cursor >= data.len() {
+            return None;
+        }
+        let shared = read_varint(data, &mut self.cursor) as usize;
+        let suffix_len = read_varint(data, &mut self.cursor) as usize;
+        self.key.truncate(shared);
+        self.key
+            .extend_from_slice(&data[self.cursor..self.cursor + suffix_len]);
+        self.cursor += suffix_len;
+        let file_id = read_varint(data, &mut self.cursor) as FileId;
+        let value_offset = read_varint(data, &mut self.cursor);
+        let value_size = read_varint(data, &mut self.cursor);
+        let check_sum = u32::from_be_bytes(data[self.cursor..self.cursor + 4].try_into().unwrap());
+        let flags = data[self.cursor + 4];
+        self.cursor += 5;
+        let entry = MemIndexEntry {
+            file_id,
+            value_offset,
+            value_size,
+            check_sum,
+            flags,
+        };
+        Some((self.key.clone(), entry))
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], cursor: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*cursor];
+        *cursor += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
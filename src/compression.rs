@@ -0,0 +1,71 @@
+use crate::bitcask::Value;
+use crate::error::BitCaskError;
+use crate::log_entry::{FLAG_LZ4, FLAG_ZSTD};
+use std::borrow::Cow;
+
+/// How values are compressed when they are written. Records remember their codec, so a data directory can mix
+/// codecs, and compaction rewrites every value with the codec the store is opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstd at the given level, 0 being zstd's default level.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+/// Compress `value` if it is at least `threshold` bytes long and compressing makes it smaller. Return the flags of
+/// the record and the bytes to store.
+pub(crate) fn compress(
+    value: &[u8],
+    compression: Compression,
+    threshold: usize,
+) -> Result<(u8, Cow<'_, [u8]>), BitCaskError> {
+    // an empty value is a tombstone, and must stay empty
+    if value.is_empty() || value.len() < threshold {
+        return Ok((0, Cow::Borrowed(value)));
+    }
+    let compressed: Option<(u8, Vec<u8>)> = match compression {
+        Compression::None => None,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Some((FLAG_LZ4, lz4_flex::compress_prepend_size(value))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => Some((FLAG_ZSTD, zstd::bulk::compress(value, level)?)),
+    };
+    match compressed {
+        Some((flags, compressed)) if compressed.len() < value.len() => Ok((flags, Cow::Owned(compressed))),
+        _ => Ok((0, Cow::Borrowed(value))),
+    }
+}
+
+/// Turn the bytes stored in a record with the given flags back into the value.
+pub(crate) fn decompress(flags: u8, stored: Vec<u8>) -> Result<Value, BitCaskError> {
+    match flags & (FLAG_LZ4 | FLAG_ZSTD) {
+        0 => Ok(stored),
+        #[cfg(feature = "lz4")]
+        FLAG_LZ4 => lz4_flex::decompress_size_prepended(&stored)
+            .map_err(|e| BitCaskError::CorruptedData(format!("invalid lz4 data: {}", e))),
+        #[cfg(feature = "zstd")]
+        FLAG_ZSTD => zstd::stream::decode_all(stored.as_slice())
+            .map_err(|e| BitCaskError::CorruptedData(format!("invalid zstd data: {}", e))),
+        #[cfg(not(feature = "lz4"))]
+        FLAG_LZ4 => Err(unsupported("lz4")),
+        #[cfg(not(feature = "zstd"))]
+        FLAG_ZSTD => Err(unsupported("zstd")),
+        flags => Err(BitCaskError::CorruptedData(format!(
+            "invalid compression flags {:#04x}",
+            flags
+        ))),
+    }
+}
+
+#[cfg(not(all(feature = "lz4", feature = "zstd")))]
+fn unsupported(codec: &str) -> BitCaskError {
+    BitCaskError::UnexpectedError(anyhow::anyhow!(
+        "value is compressed with {}, enable the `{}` feature to read it",
+        codec,
+        codec
+    ))
+}
@@ -1,27 +1,44 @@
-use crate::bitcask::{FileId, Key, Value};
+use crate::bitcask::{FileId, Options, Value};
+use crate::blob_file::{BlobFile, BlobPointer};
 use crate::error::BitCaskError;
-use crate::log_entry::DiskLogEntry;
+use crate::events::{Event, Events};
+use crate::log_entry::{checksum, DiskLogEntry, DiskLogEntryRef, FLAG_BLOB, FLAG_ENCRYPTED, FLAG_LZ4, FLAG_ZSTD};
 use crate::log_file::DiskLogFile;
-use crate::memory_index::{MemIndex, MemIndexEntry};
+use crate::memory_index::{Index, MemIndexEntry, Tombstones};
+use crate::metrics::Metrics;
+use crate::snapshot;
+use crate::stream::{StagedValue, StagingFile, ValueReader};
+use crate::value_format::ValueFormat;
+use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::path::PathBuf;
-use tracing::trace;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::field::Empty;
+use tracing::{instrument, trace, warn, Span};
 
 pub(crate) struct DiskLog {
     files: Vec<DiskLogFile>,
     data_dir: PathBuf,
     current_file_size: u64,
     immutable: bool,
+    sync: bool,
+    value_format: ValueFormat,
+    /// The last blob file is open for appending, like the last log file.
+    blob_files: Vec<BlobFile>,
+    blob_threshold: Option<usize>,
+    metrics: Arc<Metrics>,
+    events: Arc<Events>,
 }
 
 impl DiskLog {
     /// Initialize the disk log from the immutable files. This method is called when compaction is started.
     pub(crate) fn immutable_initialization(
         immutable_files: Vec<PathBuf>,
-        mem_index: &mut MemIndex,
+        mem_index: &mut dyn Index,
+        tombstones: &mut Tombstones,
     ) -> Result<Self, BitCaskError> {
-        let files = Self::to_disk_log_files(immutable_files, mem_index)?;
+        let files = Self::to_disk_log_files(immutable_files, mem_index, tombstones)?;
         let data_dir = files.first().unwrap().path.parent().unwrap().to_path_buf();
 
         Ok(Self {
@@ -29,6 +46,12 @@ impl DiskLog {
             data_dir,
             current_file_size: 0,
             immutable: true,
+            sync: false,
+            value_format: ValueFormat::new(&Options::default()),
+            blob_files: Vec::new(),
+            blob_threshold: None,
+            metrics: Arc::default(),
+            events: Arc::default(),
         })
     }
 
@@ -40,29 +63,72 @@ impl DiskLog {
             data_dir: data_dir_path_buf,
             current_file_size: 0,
             immutable: false,
+            sync: false,
+            value_format: ValueFormat::new(&Options::default()),
+            blob_files: Vec::new(),
+            blob_threshold: None,
+            metrics: Arc::default(),
+            events: Arc::default(),
         })
     }
 
     /// If the data directory is empty, create a new log file with file id 0.
-    /// Otherwise, load all the log files from disk and populate the memory index.
+    /// Otherwise, load all the log files from disk and populate the memory index, from the index snapshot if the log
+    /// files did not change since it was written. With `truncate_torn_tail`, a record that was only partly appended to
+    /// the last log file is cut off first.
     pub(crate) fn from_disk<T: Into<PathBuf>>(
         data_dir: T,
-        mem_index: &mut MemIndex,
+        mem_index: &mut dyn Index,
+        tombstones: &mut Tombstones,
+        truncate_torn_tail: bool,
+        events: Arc<Events>,
     ) -> Result<Self, BitCaskError> {
         let data_dir: PathBuf = data_dir.into();
 
-        let files = std::fs::read_dir(&data_dir)?
-            .filter_map(|path| {
-                path.ok().map(|path| path.path()).filter(|path| {
-                    path.is_file() && path.extension() == Some(OsStr::new(DiskLogFile::EXT))
+        let files = Self::sorted_by_file_id(
+            std::fs::read_dir(&data_dir)?
+                .filter_map(|path| {
+                    path.ok().map(|path| path.path()).filter(|path| {
+                        path.is_file() && path.extension() == Some(OsStr::new(DiskLogFile::EXT))
+                    })
                 })
-            })
-            .collect();
-        let files = Self::to_disk_log_files(files, mem_index)?;
+                .collect(),
+        );
+        // only the last file was being appended to, a record cut short in any other file is damage for repair
+        if let Some((file_id, path)) = files.last().filter(|_| truncate_torn_tail) {
+            if let Some((offset, bytes)) = events.detect_corruption(DiskLogFile::truncate_torn_tail(path))? {
+                warn!(
+                    "Cut off {} bytes of a partly written record at offset {} of {:?}",
+                    bytes, offset, path
+                );
+                events.emit(Event::RecoveryTruncated {
+                    file_id: *file_id,
+                    offset,
+                    bytes,
+                });
+            }
+        }
+        let file_sizes = files
+            .iter()
+            .map(|(file_id, path)| Ok((*file_id, std::fs::metadata(path)?.len())))
+            .collect::<Result<Vec<_>, BitCaskError>>()?;
+        let files = if !files.is_empty() && snapshot::load(&data_dir, &file_sizes, mem_index, tombstones)? {
+            trace!("Loaded the index from its snapshot");
+            files
+                .into_iter()
+                .map(|(file_id, path)| DiskLogFile::open_without_replay(file_id, path))
+                .collect::<Result<Vec<_>, BitCaskError>>()?
+        } else {
+            events.detect_corruption(Self::replay_files(files, mem_index, tombstones))?
+        };
+        let blob_files = BlobFile::open_all(&data_dir)?;
 
-        if (files.len()) == 0 {
+        if files.is_empty() {
             trace!("No disk log files found, starting from scratch");
-            return Self::new(data_dir);
+            let mut disk_log = Self::new(data_dir)?;
+            disk_log.blob_files = blob_files;
+            disk_log.events = events;
+            return Ok(disk_log);
         }
 
         let current_file_size = files.last().unwrap().file.metadata()?.len();
@@ -72,6 +138,12 @@ impl DiskLog {
             data_dir,
             current_file_size,
             immutable: false,
+            sync: false,
+            value_format: ValueFormat::new(&Options::default()),
+            blob_files,
+            blob_threshold: None,
+            metrics: Arc::default(),
+            events,
         })
     }
 
@@ -83,14 +155,114 @@ impl DiskLog {
     }
 
     fn get_file(&self, file_id: FileId) -> &DiskLogFile {
-        self.files.get(file_id as usize).unwrap()
+        // file ids are sorted but not contiguous, compaction leaves a gap after the compacted file
+        let index = self
+            .files
+            .binary_search_by_key(&file_id, |disk_log_file| disk_log_file.file_id)
+            .unwrap();
+        &self.files[index]
+    }
+
+    fn get_blob_file(&self, file_id: FileId) -> Result<&BlobFile, BitCaskError> {
+        self.blob_files
+            .binary_search_by_key(&file_id, |blob_file| blob_file.file_id)
+            .map(|index| &self.blob_files[index])
+            .map_err(|_| BitCaskError::CorruptedData(format!("missing blob file {}", file_id)))
+    }
+
+    pub(crate) fn get(&self, key: &[u8], mem_index_entry: &MemIndexEntry) -> Result<Value, BitCaskError> {
+        let (mem_index_entry, stored) =
+            self.follow_blob(mem_index_entry, self.get_stored(mem_index_entry)?)?;
+        self.value_format.decode(key, &mem_index_entry, stored)
+    }
+
+    /// Like [`DiskLog::get`], but verifies the value against the checksum kept in the index, and the value of a blob
+    /// against the checksum kept in its pointer.
+    pub(crate) fn get_verified(&self, key: &[u8], mem_index_entry: &MemIndexEntry) -> Result<Value, BitCaskError> {
+        let stored = self.get_stored(mem_index_entry)?;
+        if checksum(&stored) != mem_index_entry.check_sum {
+            return Err(BitCaskError::CorruptedData(format!(
+                "invalid checksum of the value at offset {} of file {}",
+                mem_index_entry.value_offset, mem_index_entry.file_id
+            )));
+        }
+        let (blob_entry, stored) = self.follow_blob(mem_index_entry, stored)?;
+        if blob_entry != *mem_index_entry && checksum(&stored) != blob_entry.check_sum {
+            return Err(BitCaskError::CorruptedData(format!(
+                "invalid checksum of the value at offset {} of blob file {}",
+                blob_entry.value_offset, blob_entry.file_id
+            )));
+        }
+        self.value_format.decode(key, &blob_entry, stored)
+    }
+
+    /// If the record of `mem_index_entry` points to a blob, read the blob instead, and describe where it is stored
+    /// with an entry of the blob file.
+    fn follow_blob(
+        &self,
+        mem_index_entry: &MemIndexEntry,
+        stored: Vec<u8>,
+    ) -> Result<(MemIndexEntry, Vec<u8>), BitCaskError> {
+        if mem_index_entry.flags & FLAG_BLOB == 0 {
+            return Ok((*mem_index_entry, stored));
+        }
+        let pointer = BlobPointer::decode(&stored)?;
+        let blob = self.get_blob_file(pointer.file_id)?.read(&pointer)?;
+        self.metrics.add_read(pointer.size);
+        let blob_entry = MemIndexEntry {
+            file_id: pointer.file_id,
+            value_offset: pointer.offset,
+            value_size: pointer.size,
+            check_sum: pointer.check_sum,
+            flags: mem_index_entry.flags,
+        };
+        Ok((blob_entry, blob))
+    }
+
+    /// A reader of the value of `mem_index_entry`. Values stored as they are, in a log file or a blob file, are read
+    /// from their file as the reader goes; the others are decoded in memory first.
+    pub(crate) fn get_reader(
+        &self,
+        key: &[u8],
+        mem_index_entry: &MemIndexEntry,
+        verify: bool,
+    ) -> Result<ValueReader, BitCaskError> {
+        if mem_index_entry.flags & (FLAG_LZ4 | FLAG_ZSTD | FLAG_ENCRYPTED) != 0 {
+            let value = if verify {
+                self.get_verified(key, mem_index_entry)?
+            } else {
+                self.get(key, mem_index_entry)?
+            };
+            return Ok(ValueReader::decoded(value));
+        }
+        let (file, offset, len, check_sum) = if mem_index_entry.flags & FLAG_BLOB != 0 {
+            let pointer = self.blob_pointer(mem_index_entry)?;
+            let blob_file = self.get_blob_file(pointer.file_id)?;
+            (&blob_file.file, pointer.offset, pointer.size, pointer.check_sum)
+        } else {
+            let disk_log_file = self.get_file(mem_index_entry.file_id);
+            (
+                &disk_log_file.file,
+                mem_index_entry.value_offset,
+                mem_index_entry.value_size,
+                mem_index_entry.check_sum,
+            )
+        };
+        Ok(ValueReader::region(file.try_clone()?, offset, len, verify.then_some(check_sum)))
     }
 
-    pub(crate) fn get(&self, mem_index_entry: &MemIndexEntry) -> Result<Value, BitCaskError> {
+    /// Read the pointer stored in the record of a blob.
+    pub(crate) fn blob_pointer(&self, mem_index_entry: &MemIndexEntry) -> Result<BlobPointer, BitCaskError> {
+        BlobPointer::decode(&self.get_stored(mem_index_entry)?)
+    }
+
+    /// Read the value as it is stored, i.e. still compressed if the record is, and a pointer if it is a blob.
+    pub(crate) fn get_stored(&self, mem_index_entry: &MemIndexEntry) -> Result<Vec<u8>, BitCaskError> {
         let MemIndexEntry {
             value_offset,
             value_size,
             file_id,
+            ..
         } = mem_index_entry;
         let disk_log_file = self.get_file(*file_id);
         let mut buffered_reader =
@@ -98,35 +270,262 @@ impl DiskLog {
         buffered_reader.seek(SeekFrom::Start(*value_offset))?;
         let mut buf = vec![0u8; *value_size as usize];
         buffered_reader.read_exact(buf.as_mut())?;
-        Ok(Value::from(buf))
+        self.metrics.add_read(*value_size);
+        Ok(buf)
+    }
+
+    pub(crate) fn put(&mut self, key: &[u8], value: &[u8]) -> Result<MemIndexEntry, BitCaskError> {
+        self.append(DiskLogEntryRef::new_entry(key, value))
     }
 
-    pub(crate) fn put(&mut self, key: &Key, value: &Value) -> Result<MemIndexEntry, BitCaskError> {
-        self.append(DiskLogEntry::new_entry(key.clone(), value.clone()))
+    pub(crate) fn delete(&mut self, key: &[u8]) -> Result<MemIndexEntry, BitCaskError> {
+        self.append(DiskLogEntryRef::new_tombstone(key))
     }
 
-    pub(crate) fn delete(&mut self, key: &Key) -> Result<MemIndexEntry, BitCaskError> {
-        self.append(DiskLogEntry::new_tombstone(key.clone()))
+    /// Whether values are stored as they are, which lets them be staged in a file before they are put.
+    pub(crate) fn stores_values_as_is(&self) -> bool {
+        self.value_format.is_identity()
     }
 
-    fn append(&mut self, entry: DiskLogEntry) -> Result<MemIndexEntry, BitCaskError> {
+    /// Put a value read before the storage was locked. A staging file above the blob threshold becomes a blob file of
+    /// its own, without being copied; one below it is copied to the current log file.
+    pub(crate) fn put_staged(&mut self, key: &[u8], staged: StagedValue) -> Result<MemIndexEntry, BitCaskError> {
         if self.immutable {
             panic!("Cannot append to an immutable disk log");
         }
+        let mut staging_file = match staged {
+            StagedValue::Memory(value) => return self.put(key, &value),
+            StagedValue::File(staging_file) => staging_file,
+        };
+        let (sync, len) = (self.sync, staging_file.len);
+        if self.is_blob(len) {
+            let pointer = self.adopt_blob(&mut staging_file)?;
+            self.metrics.add_written(len);
+            let pointer = pointer.encode();
+            let entry = DiskLogEntryRef::new_entry(key, &pointer).with_flags(FLAG_BLOB);
+            return Ok(self.append_stored(&[entry])?.pop().unwrap());
+        }
         let (disk_log_file, file_id) = self.current_file();
-        let value_offset = disk_log_file.append_new_entry(entry.clone())?;
-        self.current_file_size += entry.total_byte_size();
-        // check if the current file exceeds the max file size, if so, create a new file
+        let (value_offset, check_sum) = disk_log_file.append_from_reader(key, staging_file.reader()?, len)?;
+        if sync {
+            disk_log_file.file.sync_data()?;
+        }
+        let record_size = DiskLogEntry::header_byte_size() + key.len() as u64 + len;
+        self.metrics.add_written(record_size);
+        self.current_file_size += record_size;
         if self.current_file_size > DiskLogFile::MAX_FILE_SIZE {
             self.check_file_size()?;
         }
         Ok(MemIndexEntry {
             file_id,
             value_offset,
-            value_size: entry.value_byte_size(),
+            value_size: len,
+            check_sum,
+            flags: 0,
         })
     }
 
+    /// Make `staging_file` the current blob file. If it cannot be moved next to the blob files, e.g. because a
+    /// compaction to another file system finished while the value was staged, the value is copied instead.
+    fn adopt_blob(&mut self, staging_file: &mut StagingFile) -> Result<BlobPointer, BitCaskError> {
+        if self.sync {
+            staging_file.file.sync_data()?;
+        }
+        let file_id = self.blob_files.last().map_or(0, |blob_file| blob_file.file_id + 1);
+        match BlobFile::from_staging_file(&self.data_dir, file_id, staging_file) {
+            Ok((blob_file, pointer)) => {
+                self.blob_files.push(blob_file);
+                Ok(pointer)
+            }
+            Err(e) => {
+                warn!("Copying a staged blob instead of moving it: {}", e);
+                let sync = self.sync;
+                let blob_file = self.current_blob_file()?;
+                let pointer = blob_file.append_from_reader(staging_file.reader()?, staging_file.len)?;
+                if sync {
+                    blob_file.file.sync_data()?;
+                }
+                Ok(pointer)
+            }
+        }
+    }
+
+    fn append(&mut self, entry: DiskLogEntryRef<'_>) -> Result<MemIndexEntry, BitCaskError> {
+        Ok(self.append_batch(&[entry])?.pop().unwrap())
+    }
+
+    /// Apply the options that affect how records are appended.
+    pub(crate) fn configure(&mut self, options: &Options) {
+        self.sync = options.sync;
+        self.value_format = ValueFormat::new(options);
+        self.blob_threshold = options.blob_threshold;
+    }
+
+    /// Count the work of this disk log in `metrics`, which outlive it.
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Fail if an encrypted value of `mem_index` cannot be decrypted, which means that the data directory was opened
+    /// with the wrong key, or without one.
+    pub(crate) fn check_encryption_key(&self, mem_index: &dyn Index) -> Result<(), BitCaskError> {
+        let encrypted = mem_index
+            .iter()
+            .find(|(_, mem_index_entry)| mem_index_entry.flags & FLAG_ENCRYPTED != 0);
+        if let Some((key, mem_index_entry)) = encrypted {
+            self.get(&key, &mem_index_entry)?;
+        }
+        Ok(())
+    }
+
+    /// Append all the entries to the current file with a single write. The file is only rotated after the whole batch,
+    /// so a batch is never split across files. Values are compressed according to the options, and the ones above
+    /// the blob threshold are written to the current blob file first.
+    pub(crate) fn append_batch(
+        &mut self,
+        entries: &[DiskLogEntryRef<'_>],
+    ) -> Result<Vec<MemIndexEntry>, BitCaskError> {
+        if self.immutable {
+            panic!("Cannot append to an immutable disk log");
+        }
+        let mut stored_values = Vec::with_capacity(entries.len());
+        for entry in entries {
+            stored_values.push(match entry.value {
+                // an empty value is a delete, and is written as a tombstone whatever the format
+                Some([]) | None => (0, Cow::Borrowed(&[][..])),
+                Some(value) if self.is_blob(value.len() as u64) => self.put_blob(entry.key, value)?,
+                Some(value) => self.value_format.encode(entry.key, value)?,
+            });
+        }
+        let stored_entries: Vec<DiskLogEntryRef> = entries
+            .iter()
+            .zip(&stored_values)
+            .map(|(entry, (flags, value))| match flags {
+                0 => *entry,
+                _ => DiskLogEntryRef::new_entry(entry.key, value).with_flags(*flags),
+            })
+            .collect();
+        // a record must not point to a blob that could be lost
+        if self.sync && stored_entries.iter().any(|entry| entry.flags & FLAG_BLOB != 0) {
+            self.current_blob_file()?.file.sync_data()?;
+        }
+        self.append_stored(&stored_entries)
+    }
+
+    /// Append entries whose values are already stored as they should be, with a single write.
+    pub(crate) fn append_stored(
+        &mut self,
+        entries: &[DiskLogEntryRef<'_>],
+    ) -> Result<Vec<MemIndexEntry>, BitCaskError> {
+        if self.immutable {
+            panic!("Cannot append to an immutable disk log");
+        }
+        let sync = self.sync;
+        let (disk_log_file, file_id) = self.current_file();
+        let value_offsets = disk_log_file.append_new_entries(entries)?;
+        if sync {
+            disk_log_file.file.sync_data()?;
+        }
+        let written = entries.iter().map(|e| e.total_byte_size()).sum::<u64>();
+        self.metrics.add_written(written);
+        self.current_file_size += written;
+        if self.current_file_size > DiskLogFile::MAX_FILE_SIZE {
+            self.check_file_size()?;
+        }
+        Ok(entries
+            .iter()
+            .zip(value_offsets)
+            .map(|(entry, value_offset)| MemIndexEntry {
+                file_id,
+                value_offset,
+                value_size: entry.value_byte_size(),
+                check_sum: entry.check_sum,
+                flags: entry.flags,
+            })
+            .collect())
+    }
+
+    fn is_blob(&self, len: u64) -> bool {
+        // empty values are tombstones, they always stay in the log
+        matches!(self.blob_threshold, Some(threshold) if len > 0 && len >= threshold as u64)
+    }
+
+    /// Write the encoded `value` of `key` to the current blob file, and return the flags and the value of the record
+    /// that points to it.
+    fn put_blob(&mut self, key: &[u8], value: &[u8]) -> Result<(u8, Cow<'static, [u8]>), BitCaskError> {
+        let (flags, stored) = self.value_format.encode(key, value)?;
+        let pointer = self.current_blob_file()?.append(&stored)?;
+        self.metrics.add_written(pointer.size);
+        Ok((flags | FLAG_BLOB, Cow::Owned(pointer.encode().to_vec())))
+    }
+
+    /// The blob file open for appending. The first one is created along with the first blob, and a new one once the
+    /// current one is full.
+    fn current_blob_file(&mut self) -> Result<&mut BlobFile, BitCaskError> {
+        let full = match self.blob_files.last() {
+            Some(blob_file) => blob_file.size()? > BlobFile::MAX_FILE_SIZE,
+            None => true,
+        };
+        if full {
+            self.create_new_blob_file()?;
+        }
+        Ok(self.blob_files.last_mut().unwrap())
+    }
+
+    /// Start a new blob file, so that the previous one can be garbage-collected.
+    #[instrument(level = "info", skip_all, fields(file_id = Empty))]
+    pub(crate) fn create_new_blob_file(&mut self) -> Result<(), BitCaskError> {
+        if let Some(blob_file) = self.blob_files.last() {
+            if self.sync {
+                blob_file.file.sync_data()?;
+            }
+        }
+        let file_id = self.blob_files.last().map_or(0, |blob_file| blob_file.file_id + 1);
+        Span::current().record("file_id", file_id);
+        self.blob_files.push(BlobFile::new(&self.data_dir, file_id)?);
+        Ok(())
+    }
+
+    pub(crate) fn current_blob_file_id(&self) -> Option<FileId> {
+        self.blob_files.last().map(|blob_file| blob_file.file_id)
+    }
+
+    /// The id and the size in bytes of every blob file, in file id order.
+    pub(crate) fn blob_file_sizes(&self) -> Result<Vec<(FileId, u64)>, BitCaskError> {
+        self.blob_files
+            .iter()
+            .map(|blob_file| Ok((blob_file.file_id, blob_file.size()?)))
+            .collect()
+    }
+
+    /// Copy a blob as is to the current blob file, and return its new location.
+    pub(crate) fn move_blob(&mut self, pointer: &BlobPointer) -> Result<BlobPointer, BitCaskError> {
+        let blob = self.get_blob_file(pointer.file_id)?.read(pointer)?;
+        self.metrics.add_read(pointer.size);
+        self.metrics.add_written(pointer.size);
+        self.current_blob_file()?.append(&blob)
+    }
+
+    pub(crate) fn delete_blob_file(&mut self, file_id: FileId) -> Result<(), BitCaskError> {
+        if let Ok(index) = self
+            .blob_files
+            .binary_search_by_key(&file_id, |blob_file| blob_file.file_id)
+        {
+            let blob_file = self.blob_files.remove(index);
+            std::fs::remove_file(&blob_file.path)?;
+        }
+        Ok(())
+    }
+
+    /// fsync the current log file and the current blob file, whatever the `sync` option.
+    pub(crate) fn sync_all(&mut self) -> Result<(), BitCaskError> {
+        if let Some(blob_file) = self.blob_files.last() {
+            blob_file.file.sync_data()?;
+        }
+        self.current_file().0.file.sync_data()?;
+        Ok(())
+    }
+
     fn check_file_size(&mut self) -> Result<(), BitCaskError> {
         let (disk_log_file, file_id) = self.current_file();
         let file = &mut disk_log_file.file;
@@ -141,6 +540,14 @@ impl DiskLog {
         Ok(())
     }
 
+    /// The id and the size in bytes of every log file, in file id order.
+    pub(crate) fn file_sizes(&self) -> Result<Vec<(FileId, u64)>, BitCaskError> {
+        self.files
+            .iter()
+            .map(|disk_log_file| Ok((disk_log_file.file_id, disk_log_file.file.metadata()?.len())))
+            .collect()
+    }
+
     pub(crate) fn get_immutable_files(&self) -> Vec<PathBuf> {
         let last_file_id = self.files.last().unwrap().file_id;
         self.files
@@ -151,11 +558,17 @@ impl DiskLog {
     }
 
     /// Invoked when the user calls `compact_to_new_dir` or library call `check_file_size`.
+    #[instrument(name = "rotate", level = "info", skip_all, fields(file_id = Empty))]
     pub(crate) fn create_new_file(&mut self) -> Result<(), BitCaskError> {
         let last_file_id = self.files.last().unwrap().file_id;
         let new_file_id = last_file_id + 1;
+        Span::current().record("file_id", new_file_id);
         let new_file = DiskLogFile::new(&self.data_dir, new_file_id)?;
         self.files.push(new_file);
+        self.value_format.reset_nonces();
+        self.events.emit(Event::FileRotated {
+            file_id: new_file_id,
+        });
         Ok(())
     }
 
@@ -177,31 +590,50 @@ impl DiskLog {
             new_file.push(file.file_name().unwrap());
             std::fs::copy(file, new_file)?;
         }
+        // blobs are never rewritten by compaction, link them rather than copy them when the file system allows it
+        for blob_file in &self.blob_files {
+            let mut new_file = new_log_file_path.clone();
+            new_file.push(blob_file.path.file_name().unwrap());
+            if std::fs::hard_link(&blob_file.path, &new_file).is_err() {
+                std::fs::copy(&blob_file.path, new_file)?;
+            }
+        }
         Ok(())
     }
 
     pub(crate) fn to_disk_log_files(
         files: Vec<PathBuf>,
-        mem_index: &mut MemIndex,
+        mem_index: &mut dyn Index,
+        tombstones: &mut Tombstones,
+    ) -> Result<Vec<DiskLogFile>, BitCaskError> {
+        Self::replay_files(Self::sorted_by_file_id(files), mem_index, tombstones)
+    }
+
+    /// Open the files, which are sorted by file id, and replay their records into the index.
+    fn replay_files(
+        files: Vec<(FileId, PathBuf)>,
+        mem_index: &mut dyn Index,
+        tombstones: &mut Tombstones,
     ) -> Result<Vec<DiskLogFile>, BitCaskError> {
-        let mut files = files
+        // replay the files in the order they were written, so that later records win
+        files
             .into_iter()
-            .filter_map(|path| {
-                path.file_stem()
-                    .and_then(|file_stem| file_stem.to_str())
-                    .and_then(|file_stem| file_stem.parse::<FileId>().ok())
-                    .map(|file_id| (file_id, path))
-            })
-            .map(|(file_id, path)| {
-                DiskLogFile::open(file_id, path, mem_index)
-                    .map(|disk_log_file| (file_id, disk_log_file))
-            })
-            .collect::<Result<Vec<(FileId, DiskLogFile)>, BitCaskError>>()?;
+            .map(|(file_id, path)| DiskLogFile::open(file_id, path, mem_index, tombstones))
+            .collect()
+    }
 
-        files.sort_by_key(|(file_id, _)| *file_id);
-        Ok(files
+    fn sorted_by_file_id(files: Vec<PathBuf>) -> Vec<(FileId, PathBuf)> {
+        let mut files: Vec<(FileId, PathBuf)> = files
             .into_iter()
-            .map(|(_, disk_log_file)| disk_log_file)
-            .collect())
+            .filter_map(|path| Self::file_id_of(&path).map(|file_id| (file_id, path)))
+            .collect();
+        files.sort_by_key(|(file_id, _)| *file_id);
+        files
+    }
+
+    fn file_id_of(path: &Path) -> Option<FileId> {
+        path.file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .and_then(|file_stem| file_stem.parse::<FileId>().ok())
     }
 }
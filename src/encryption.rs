@@ -0,0 +1,93 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::Rng;
+use std::fmt::{Debug, Formatter};
+
+const NONCE_PREFIX_SIZE: usize = 16;
+const NONCE_SIZE: usize = NONCE_PREFIX_SIZE + 8;
+
+/// A 256-bit key to encrypt values at rest with XChaCha20-Poly1305.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// A random key.
+    pub fn generate() -> Self {
+        Self(rand::thread_rng().gen())
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // never print the key itself
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Encrypts values with the current key, and decrypts them with the current key or any of the old ones.
+///
+/// An encrypted value is stored as the nonce followed by the ciphertext and its tag. The key of the record is
+/// authenticated along with the value, so that a value cannot be moved to another key unnoticed; keys themselves are
+/// stored in clear. A nonce is made of a random
+/// prefix, drawn whenever a log file is opened for appending, and a counter of the values encrypted since. The
+/// prefix is large enough for a collision between two files to be out of the question.
+#[derive(Clone)]
+pub(crate) struct Cipher {
+    current: XChaCha20Poly1305,
+    old: Vec<XChaCha20Poly1305>,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u64,
+}
+
+impl Cipher {
+    pub(crate) fn new(key: &EncryptionKey, old_keys: &[EncryptionKey]) -> Self {
+        Self {
+            current: XChaCha20Poly1305::new(&key.0.into()),
+            old: old_keys
+                .iter()
+                .map(|key| XChaCha20Poly1305::new(&key.0.into()))
+                .collect(),
+            nonce_prefix: rand::thread_rng().gen(),
+            counter: 0,
+        }
+    }
+
+    /// Start a new sequence of nonces, for a log file that is opened for appending.
+    pub(crate) fn reset_nonces(&mut self) {
+        self.nonce_prefix = rand::thread_rng().gen();
+        self.counter = 0;
+    }
+
+    pub(crate) fn encrypt(&mut self, key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        let ciphertext = self
+            .current
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: value, aad: key })
+            .expect("encrypting in memory cannot fail");
+        let mut stored = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&ciphertext);
+        stored
+    }
+
+    /// Decrypt the value of `key` with the first encryption key that authenticates it, or return `None` if none does.
+    pub(crate) fn decrypt(&self, key: &[u8], stored: &[u8]) -> Option<Vec<u8>> {
+        if stored.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = stored.split_at(NONCE_SIZE);
+        std::iter::once(&self.current)
+            .chain(&self.old)
+            .find_map(|cipher| {
+                let payload = Payload { msg: ciphertext, aad: key };
+                cipher.decrypt(XNonce::from_slice(nonce), payload).ok()
+            })
+    }
+}
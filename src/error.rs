@@ -12,4 +12,12 @@ pub enum BitCaskError {
     KeyExists,
     #[error("Key does not exist")]
     KeyNotFound,
+    #[error("Data is encrypted with another key")]
+    WrongEncryptionKey,
+    #[error("Data is encrypted, but no encryption key was given")]
+    MissingEncryptionKey,
+    #[error("The storage is closed")]
+    Closed,
+    #[error("A panic left the storage in an unknown state, it has to be recovered")]
+    Poisoned,
 }
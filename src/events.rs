@@ -0,0 +1,148 @@
+use crate::error::BitCaskError;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+/// Something notable the engine did, passed to every [`EventListener`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The current log file is full, or a compaction started, and a new log file is now open for appending.
+    FileRotated {
+        file_id: usize,
+    },
+    CompactionStarted {
+        immutable_files: usize,
+    },
+    CompactionFinished {
+        dir: PathBuf,
+        live_keys: usize,
+    },
+    /// A read found data that does not match its checksum, or cannot be decoded.
+    CorruptionDetected {
+        message: String,
+    },
+    /// The last record of the log was only partly written when the process stopped, and recovery cut it off because
+    /// `Options::truncate_torn_tail` is set.
+    RecoveryTruncated {
+        file_id: usize,
+        offset: u64,
+        bytes: u64,
+    },
+    /// An error that no caller sees, like a failed read behind [`crate::bitcask::KVStorage::get`], which reports the
+    /// key as missing instead.
+    BackgroundError {
+        message: String,
+    },
+}
+
+/// Receives the [`Event`]s of a [`crate::bitcask::BitCask`], see [`crate::bitcask::Options::event_listener`].
+pub trait EventListener: Send + Sync {
+    fn on_event(&self, event: &Event);
+}
+
+impl Debug for dyn EventListener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventListener")
+    }
+}
+
+/// Where the listeners are called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EventDelivery {
+    /// On the thread that caused the event, before the operation returns. A slow listener slows the engine down, and
+    /// may be called with the storage locked, so it must not use the [`crate::bitcask::BitCask`] itself.
+    #[default]
+    Sync,
+    /// On a background thread, in the order the events happened.
+    Background,
+}
+
+/// Passes events on to the listeners.
+#[derive(Default)]
+pub(crate) struct Events {
+    listeners: Vec<Arc<dyn EventListener>>,
+    delivery: EventDelivery,
+    /// The queue of the background thread and the thread itself, until they are stopped. The thread ends once the
+    /// queue is dropped.
+    background: Mutex<Option<(Sender<Event>, JoinHandle<()>)>>,
+}
+
+impl Events {
+    pub(crate) fn new(listeners: Vec<Arc<dyn EventListener>>, delivery: EventDelivery) -> Self {
+        if listeners.is_empty() || delivery == EventDelivery::Sync {
+            return Self {
+                listeners,
+                delivery,
+                background: Mutex::new(None),
+            };
+        }
+        let (sender, receiver) = channel::<Event>();
+        let background_listeners = listeners.clone();
+        let thread = std::thread::Builder::new()
+            .name("bitcask-events".to_string())
+            .spawn(move || {
+                for event in receiver {
+                    for listener in &background_listeners {
+                        listener.on_event(&event);
+                    }
+                }
+            })
+            .expect("failed to spawn the event thread");
+        Self {
+            listeners,
+            delivery,
+            background: Mutex::new(Some((sender, thread))),
+        }
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        if self.listeners.is_empty() {
+            return;
+        }
+        match self.delivery {
+            EventDelivery::Sync => {
+                for listener in &self.listeners {
+                    listener.on_event(&event);
+                }
+            }
+            // events are dropped once the thread is stopped, or if a listener panicked on it
+            EventDelivery::Background => {
+                if let Some((sender, _)) = &*self
+                    .background
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                {
+                    let _ = sender.send(event);
+                }
+            }
+        }
+    }
+
+    /// Stop the background thread once it delivered the events emitted so far.
+    pub(crate) fn stop(&self) {
+        let background = self
+            .background
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some((sender, thread)) = background {
+            drop(sender);
+            let _ = thread.join();
+        }
+    }
+
+    /// Report a `CorruptedData` error as [`Event::CorruptionDetected`] on its way to the caller.
+    pub(crate) fn detect_corruption<T>(
+        &self,
+        result: Result<T, BitCaskError>,
+    ) -> Result<T, BitCaskError> {
+        if let Err(BitCaskError::CorruptedData(message)) = &result {
+            self.emit(Event::CorruptionDetected {
+                message: message.clone(),
+            });
+        }
+        result
+    }
+}
@@ -0,0 +1,130 @@
+use crate::bitcask::{Key, Value};
+use crate::error::BitCaskError;
+use anyhow::{anyhow, Context};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// How keys and values are represented as text in a dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Keys and values are written as is, exporting fails if they are not valid UTF-8.
+    Utf8,
+    /// Keys and values are written in standard base64 with padding.
+    Base64,
+}
+
+/// The format of a dump written by `BitCask::export` and read by `BitCask::import`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One `{"key": ..., "value": ...}` JSON object per line.
+    JsonLines(Encoding),
+    /// A `key,value` header followed by one row per key.
+    Csv(Encoding),
+}
+
+impl ExportFormat {
+    fn encoding(&self) -> Encoding {
+        match self {
+            ExportFormat::JsonLines(encoding) | ExportFormat::Csv(encoding) => *encoding,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    key: String,
+    value: String,
+}
+
+fn encode(bytes: &[u8], encoding: Encoding) -> Result<String, BitCaskError> {
+    match encoding {
+        Encoding::Utf8 => Ok(std::str::from_utf8(bytes)
+            .context("data is not valid utf-8, export it as base64 instead")?
+            .to_string()),
+        Encoding::Base64 => Ok(BASE64.encode(bytes)),
+    }
+}
+
+fn decode(text: String, encoding: Encoding) -> Result<Vec<u8>, BitCaskError> {
+    match encoding {
+        Encoding::Utf8 => Ok(text.into_bytes()),
+        Encoding::Base64 => Ok(BASE64.decode(text).context("data is not valid base64")?),
+    }
+}
+
+/// Writes key-value pairs to a dump.
+pub(crate) enum RecordWriter<W: Write> {
+    JsonLines(W, Encoding),
+    Csv(Box<csv::Writer<W>>, Encoding),
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub(crate) fn new(writer: W, format: ExportFormat) -> Result<Self, BitCaskError> {
+        match format {
+            ExportFormat::JsonLines(encoding) => Ok(RecordWriter::JsonLines(writer, encoding)),
+            ExportFormat::Csv(encoding) => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record(["key", "value"]).map_err(anyhow::Error::from)?;
+                Ok(RecordWriter::Csv(Box::new(writer), encoding))
+            }
+        }
+    }
+
+    pub(crate) fn write(&mut self, key: &[u8], value: &[u8]) -> Result<(), BitCaskError> {
+        match self {
+            RecordWriter::JsonLines(writer, encoding) => {
+                let record = Record {
+                    key: encode(key, *encoding)?,
+                    value: encode(value, *encoding)?,
+                };
+                serde_json::to_writer(&mut *writer, &record).map_err(anyhow::Error::from)?;
+                writer.write_all(b"\n")?;
+            }
+            RecordWriter::Csv(writer, encoding) => {
+                writer
+                    .write_record([encode(key, *encoding)?, encode(value, *encoding)?])
+                    .map_err(anyhow::Error::from)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<(), BitCaskError> {
+        match self {
+            RecordWriter::JsonLines(mut writer, _) => writer.flush()?,
+            RecordWriter::Csv(mut writer, _) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Read the key-value pairs of a dump, lazily.
+pub(crate) fn read_records<'a, R: Read + 'a>(
+    reader: R,
+    format: ExportFormat,
+) -> Box<dyn Iterator<Item = Result<(Key, Value), BitCaskError>> + 'a> {
+    let encoding = format.encoding();
+    let decode_record = move |record: Record| -> Result<(Key, Value), BitCaskError> {
+        Ok((decode(record.key, encoding)?, decode(record.value, encoding)?))
+    };
+    match format {
+        ExportFormat::JsonLines(_) => Box::new(
+            BufReader::new(reader)
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(move |(line_number, line)| {
+                    let record = serde_json::from_str::<Record>(&line?)
+                        .map_err(|e| anyhow!("line {}: {}", line_number + 1, e))?;
+                    decode_record(record)
+                }),
+        ),
+        ExportFormat::Csv(_) => Box::new(
+            csv::Reader::from_reader(reader)
+                .into_deserialize::<Record>()
+                .map(move |record| decode_record(record.map_err(anyhow::Error::from)?)),
+        ),
+    }
+}
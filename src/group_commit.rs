@@ -0,0 +1,144 @@
+use crate::bitcask::{Key, PutOption, Value};
+use crate::error::BitCaskError;
+use crate::storage::{write_lock, LogIndexStorage};
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError, RwLock};
+
+/// A write waiting in the group commit queue.
+pub(crate) enum WriteOp {
+    Put {
+        key: Key,
+        value: Value,
+        nx: bool,
+        xx: bool,
+    },
+    Delete {
+        key: Key,
+    },
+}
+
+impl WriteOp {
+    pub(crate) fn put(key: &[u8], value: &[u8], option: Option<PutOption>) -> Self {
+        let (nx, xx) = option.map_or((false, false), |option| (option.nx, option.xx));
+        WriteOp::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            nx,
+            xx,
+        }
+    }
+
+    pub(crate) fn delete(key: &[u8]) -> Self {
+        WriteOp::Delete { key: key.to_vec() }
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        match self {
+            WriteOp::Put { key, .. } | WriteOp::Delete { key } => key,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    next_ticket: u64,
+    pending: Vec<(u64, WriteOp)>,
+    results: HashMap<u64, Result<(), BitCaskError>>,
+    leader_active: bool,
+}
+
+/// Batches the writes of concurrent writers. The first writer to find no leader becomes the leader: it takes every
+/// queued write, applies them under a single acquisition of the storage lock with one write to the log (and one fsync
+/// if enabled), then hands every waiter its result. Writers that arrive meanwhile queue up for the next batch.
+#[derive(Default)]
+pub(crate) struct GroupCommit {
+    queue: Mutex<Queue>,
+    done: Condvar,
+}
+
+impl GroupCommit {
+    /// Queue `op` and block until it is durable, or failed.
+    pub(crate) fn submit(&self, storage: &RwLock<LogIndexStorage>, op: WriteOp) -> Result<(), BitCaskError> {
+        let mut queue = self.lock_queue();
+        let ticket = queue.next_ticket;
+        queue.next_ticket += 1;
+        queue.pending.push((ticket, op));
+        loop {
+            if let Some(result) = queue.results.remove(&ticket) {
+                return result;
+            }
+            if queue.leader_active {
+                queue = self.done.wait(queue).unwrap_or_else(PoisonError::into_inner);
+                continue;
+            }
+            queue.leader_active = true;
+            let (tickets, ops): (Vec<u64>, Vec<WriteOp>) = std::mem::take(&mut queue.pending).into_iter().unzip();
+            drop(queue);
+
+            let mut batch = Batch {
+                group_commit: self,
+                ticket,
+                tickets,
+                results: None,
+            };
+            batch.results = Some(match write_lock(storage) {
+                Ok(mut storage) => storage.apply_writes(&ops),
+                Err(e) => ops.iter().map(|_| Err(copy_error(&e))).collect(),
+            });
+            drop(batch);
+
+            queue = self.lock_queue();
+        }
+    }
+
+    /// The queue is only held for bookkeeping that does not panic, so it is consistent even if it is poisoned.
+    fn lock_queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The batch of a leader, which hands the results to the writers of the batch once it is dropped. If the leader
+/// panicked while applying the batch, the writers get `Poisoned` rather than wait forever, and the next writer becomes
+/// the leader.
+struct Batch<'a> {
+    group_commit: &'a GroupCommit,
+    /// The ticket of the leader, which unwinds instead of waiting for its result.
+    ticket: u64,
+    tickets: Vec<u64>,
+    results: Option<Vec<Result<(), BitCaskError>>>,
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        let tickets = std::mem::take(&mut self.tickets);
+        let results: Vec<Result<(), BitCaskError>> = match self.results.take() {
+            Some(results) => results,
+            None => tickets.iter().map(|_| Err(BitCaskError::Poisoned)).collect(),
+        };
+        let panicked = std::thread::panicking();
+        let mut queue = self.group_commit.lock_queue();
+        queue.leader_active = false;
+        queue.results.extend(
+            tickets
+                .into_iter()
+                .zip(results)
+                .filter(|(ticket, _)| !panicked || *ticket != self.ticket),
+        );
+        self.group_commit.done.notify_all();
+    }
+}
+
+/// A copy of `error` for every write of a batch that failed as a whole.
+pub(crate) fn copy_error(error: &BitCaskError) -> BitCaskError {
+    match error {
+        BitCaskError::IoError(e) => BitCaskError::IoError(std::io::Error::new(e.kind(), e.to_string())),
+        BitCaskError::CorruptedData(message) => BitCaskError::CorruptedData(message.clone()),
+        BitCaskError::UnexpectedError(e) => BitCaskError::UnexpectedError(anyhow::anyhow!("{:#}", e)),
+        BitCaskError::KeyExists => BitCaskError::KeyExists,
+        BitCaskError::KeyNotFound => BitCaskError::KeyNotFound,
+        BitCaskError::WrongEncryptionKey => BitCaskError::WrongEncryptionKey,
+        BitCaskError::MissingEncryptionKey => BitCaskError::MissingEncryptionKey,
+        BitCaskError::Closed => BitCaskError::Closed,
+        BitCaskError::Poisoned => BitCaskError::Poisoned,
+    }
+}
@@ -0,0 +1,75 @@
+use crate::bitcask::{Key, Value};
+use crate::error::BitCaskError;
+use crate::log_entry::{Deserialize, DiskLogEntry};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// A record of a log file, as it is laid out on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Offset of the record from the beginning of the file.
+    pub offset: u64,
+    pub check_sum: u32,
+    /// Flags of the record, e.g. the codec its value is compressed with.
+    pub flags: u8,
+    pub key_size: u64,
+    pub value_size: u64,
+    pub tombstone: bool,
+    pub key: Key,
+    /// The value as stored, i.e. still compressed if the record is.
+    pub value: Option<Value>,
+}
+
+/// Iterates over the records of a single log file, in the order they were appended.
+///
+/// The iterator stops after the first record that cannot be read, e.g. because its checksum does not match or the
+/// file ends in the middle of it.
+pub struct LogFileReader {
+    reader: BufReader<File>,
+    cursor: u64,
+    file_size: u64,
+    failed: bool,
+}
+
+impl LogFileReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BitCaskError> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        Ok(Self {
+            reader: BufReader::new(file),
+            cursor: 0,
+            file_size,
+            failed: false,
+        })
+    }
+}
+
+impl Iterator for LogFileReader {
+    type Item = Result<LogRecord, BitCaskError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.cursor >= self.file_size {
+            return None;
+        }
+        let entry = match DiskLogEntry::deserialize(&mut self.reader) {
+            Ok(entry) => entry,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        let offset = self.cursor;
+        self.cursor += entry.total_byte_size();
+        Some(Ok(LogRecord {
+            offset,
+            check_sum: entry.check_sum,
+            flags: entry.flags,
+            key_size: entry.key.len() as u64,
+            value_size: entry.value_byte_size(),
+            tombstone: entry.is_tombstone(),
+            key: entry.key,
+            value: entry.value,
+        }))
+    }
+}
@@ -1,7 +1,27 @@
+#[cfg(feature = "tokio")]
+pub mod async_bitcask;
 pub mod bitcask;
+pub mod compression;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod inspect;
+pub mod memcached;
+pub mod repair;
+pub mod typed;
+pub mod verify;
+mod blob_file;
+mod cache;
+mod compact_index;
 mod disk_logs;
+mod group_commit;
 mod log_entry;
 mod log_file;
 mod memory_index;
+mod metrics;
+mod snapshot;
 mod storage;
+mod stream;
+mod value_format;
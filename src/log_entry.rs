@@ -1,10 +1,29 @@
 use crate::bitcask::{ByteOffset, ByteSize, Key, Value};
 use crate::error::BitCaskError;
-use crc::{Crc, CRC_32_CKSUM};
+use crc::{Crc, Digest, CRC_32_CKSUM};
 use std::io::{Read, Write};
 
 const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
 
+/// Flags of a record, stored in the top byte of its key size.
+pub(crate) const FLAG_LZ4: u8 = 0b01;
+pub(crate) const FLAG_ZSTD: u8 = 0b10;
+pub(crate) const FLAG_ENCRYPTED: u8 = 0b100;
+/// The value of the record is a pointer to the stored value in a blob file.
+pub(crate) const FLAG_BLOB: u8 = 0b1000;
+const KNOWN_FLAGS: u8 = FLAG_LZ4 | FLAG_ZSTD | FLAG_ENCRYPTED | FLAG_BLOB;
+const FLAGS_SHIFT: u32 = 56;
+const KEY_SIZE_MASK: u64 = (1 << FLAGS_SHIFT) - 1;
+
+pub(crate) fn checksum(value: &[u8]) -> u32 {
+    CRC32.checksum(value)
+}
+
+/// A checksum computed piece by piece, for values that are never in memory at once.
+pub(crate) fn checksum_digest() -> Digest<'static, u32> {
+    CRC32.digest()
+}
+
 /// Any object that is readable can be deserialized
 pub(crate) trait Deserialize {
     fn deserialize<T: Read>(buf: &mut T) -> Result<Self, BitCaskError>
@@ -21,6 +40,7 @@ pub(crate) trait Serialize {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct DiskLogEntry {
     pub(crate) check_sum: u32,
+    pub(crate) flags: u8,
     pub(crate) key: Key,
     pub(crate) value: Option<Value>, // None indicates a tombstone
 }
@@ -30,6 +50,7 @@ impl DiskLogEntry {
         let check_sum = CRC32.checksum(&value);
         Self {
             check_sum,
+            flags: 0,
             key,
             value: Some(value),
         }
@@ -38,10 +59,15 @@ impl DiskLogEntry {
         let check_sum = 0;
         Self {
             check_sum,
+            flags: 0,
             key,
             value: None,
         }
     }
+    pub(crate) fn with_flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
     pub(crate) fn is_tombstone(&self) -> bool {
         self.value.is_none()
     }
@@ -67,65 +93,203 @@ impl DiskLogEntry {
     const fn size_byte_len() -> ByteSize {
         ByteSize::BITS as u64 / 8
     }
+    /// Size of the fixed-length part of a record, i.e. the checksum and the key and value sizes.
+    pub(crate) const fn header_byte_size() -> ByteSize {
+        Self::check_sum_byte_size() + Self::size_byte_len() * 2
+    }
     pub(crate) fn value_byte_offset(&self) -> ByteOffset {
-        Self::check_sum_byte_size() + Self::size_byte_len() * 2 + self.key_byte_size()
+        Self::header_byte_size() + self.key_byte_size()
     }
     pub(crate) fn total_byte_size(&self) -> ByteSize {
-        Self::check_sum_byte_size()
-            + Self::size_byte_len() * 2
-            + self.key_byte_size()
-            + self.value_byte_size()
+        Self::header_byte_size() + self.key_byte_size() + self.value_byte_size()
+    }
+    pub(crate) fn borrowed(&self) -> DiskLogEntryRef<'_> {
+        DiskLogEntryRef {
+            check_sum: self.check_sum,
+            flags: self.flags,
+            key: &self.key,
+            value: self.value.as_deref(),
+        }
+    }
+}
+
+/// DiskLogEntryRef is a borrowed view of a record, so that the write path can serialize keys and values straight
+/// from the caller's buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DiskLogEntryRef<'a> {
+    pub(crate) check_sum: u32,
+    pub(crate) flags: u8,
+    pub(crate) key: &'a [u8],
+    pub(crate) value: Option<&'a [u8]>, // None indicates a tombstone
+}
+
+impl<'a> DiskLogEntryRef<'a> {
+    pub(crate) fn new_entry(key: &'a [u8], value: &'a [u8]) -> Self {
+        Self {
+            check_sum: CRC32.checksum(value),
+            flags: 0,
+            key,
+            value: Some(value),
+        }
+    }
+    pub(crate) fn new_tombstone(key: &'a [u8]) -> Self {
+        Self {
+            check_sum: 0,
+            flags: 0,
+            key,
+            value: None,
+        }
+    }
+    pub(crate) fn with_flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+    fn key_byte_size(&self) -> ByteSize {
+        self.key.len() as u64
+    }
+    pub(crate) fn value_byte_size(&self) -> ByteSize {
+        self.value.map(|v| v.len() as u64).unwrap_or(0)
+    }
+    pub(crate) fn value_byte_offset(&self) -> ByteOffset {
+        DiskLogEntry::header_byte_size() + self.key_byte_size()
+    }
+    pub(crate) fn total_byte_size(&self) -> ByteSize {
+        DiskLogEntry::header_byte_size() + self.key_byte_size() + self.value_byte_size()
     }
 }
 
 /// Disk layout
 ///  - Checksum (4 bytes long)
-///  - Size of key in bytes (8 bytes long)
+///  - Flags (top byte) and size of key in bytes (lower 7 bytes), 8 bytes long
 ///  - Size of value in bytes (8 bytes long)
 ///  - Key
 ///  - Value (if tombstone, then value is None, and value size is 0)
-impl Serialize for DiskLogEntry {
+impl Serialize for DiskLogEntryRef<'_> {
     fn serialize<T: Write>(&self, buf: &mut T) -> Result<(), BitCaskError> {
-        let DiskLogEntry {
+        let DiskLogEntryRef {
             check_sum,
+            flags,
             key,
             value,
         } = self;
         // checksum
         buf.write_all(&check_sum.to_be_bytes())?;
-        // key size and value size
-        let key_size = self.key_byte_size();
+        // flags and key size, and value size
+        let key_size = (u64::from(*flags) << FLAGS_SHIFT) | self.key_byte_size();
         let value_size = self.value_byte_size();
         buf.write_all(&key_size.to_be_bytes())?;
         buf.write_all(&value_size.to_be_bytes())?;
         // key and value
-        buf.write_all(key.as_ref())?;
+        buf.write_all(key)?;
         if let Some(value) = value {
-            buf.write_all(value.as_ref())?;
+            buf.write_all(value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for DiskLogEntry {
+    fn serialize<T: Write>(&self, buf: &mut T) -> Result<(), BitCaskError> {
+        self.borrowed().serialize(buf)
+    }
+}
+
+/// The fixed-length part of a record on disk, see [`DiskLogEntry`] for the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DiskLogEntryHeader {
+    pub(crate) check_sum: u32,
+    pub(crate) flags: u8,
+    pub(crate) key_size: ByteSize,
+    pub(crate) value_size: ByteSize,
+}
+
+impl DiskLogEntryHeader {
+    pub(crate) const BYTE_SIZE: usize = DiskLogEntry::header_byte_size() as usize;
+
+    pub(crate) fn from_bytes(buf: &[u8; Self::BYTE_SIZE]) -> Self {
+        let key_size = ByteSize::from_be_bytes(buf[4..12].try_into().unwrap());
+        Self {
+            check_sum: u32::from_be_bytes(buf[0..4].try_into().unwrap()),
+            flags: (key_size >> FLAGS_SHIFT) as u8,
+            key_size: key_size & KEY_SIZE_MASK,
+            value_size: ByteSize::from_be_bytes(buf[12..20].try_into().unwrap()),
         }
+    }
+
+    /// Size of the whole record, including the header itself.
+    pub(crate) fn total_byte_size(&self) -> ByteSize {
+        DiskLogEntry::header_byte_size()
+            .saturating_add(self.key_size)
+            .saturating_add(self.value_size)
+    }
+
+    /// Whether the record only has flags this version knows about.
+    pub(crate) fn has_known_flags(&self) -> bool {
+        self.flags & !KNOWN_FLAGS == 0
+    }
+
+    /// Tombstones are written with a zero checksum, empty values with the checksum of nothing.
+    pub(crate) fn has_tombstone_check_sum(&self) -> bool {
+        self.value_size == 0 && (self.check_sum == 0 || self.check_sum == CRC32.checksum(&[]))
+    }
+
+    /// Whether the checksum in the header matches `value`. Tombstones carry no checksum.
+    pub(crate) fn is_valid_for(&self, value: &[u8]) -> bool {
+        value.is_empty() || self.check_sum == CRC32.checksum(value)
+    }
+}
+
+impl Serialize for DiskLogEntryHeader {
+    fn serialize<T: Write>(&self, buf: &mut T) -> Result<(), BitCaskError> {
+        let key_size = (u64::from(self.flags) << FLAGS_SHIFT) | self.key_size;
+        buf.write_all(&self.check_sum.to_be_bytes())?;
+        buf.write_all(&key_size.to_be_bytes())?;
+        buf.write_all(&self.value_size.to_be_bytes())?;
         Ok(())
     }
 }
 
+impl Deserialize for DiskLogEntryHeader {
+    fn deserialize<T: Read>(buf: &mut T) -> Result<Self, BitCaskError> {
+        let mut header_buf = [0u8; Self::BYTE_SIZE];
+        buf.read_exact(&mut header_buf)?;
+        Ok(Self::from_bytes(&header_buf))
+    }
+}
+
 impl Deserialize for DiskLogEntry {
     fn deserialize<T: Read>(buf: &mut T) -> Result<Self, BitCaskError> {
-        // 4 bytes long for holding checksum
-        let mut check_sum_buf = [0u8; Self::check_sum_byte_size() as usize];
-        buf.read_exact(&mut check_sum_buf)?;
-        let check_sum = u32::from_be_bytes(check_sum_buf);
-        // 8 bytes long for holding size
-        let mut size_buf = [0u8; Self::size_byte_len() as usize];
-        buf.read_exact(&mut size_buf)?;
-        let key_size = ByteSize::from_be_bytes(size_buf);
-        buf.read_exact(&mut size_buf)?;
-        let value_size = ByteSize::from_be_bytes(size_buf);
+        // 4 bytes long for holding checksum, 8 bytes long for holding each size
+        let header = DiskLogEntryHeader::deserialize(buf)?;
+        Self::deserialize_after_header(header, buf)
+    }
+}
+
+impl DiskLogEntry {
+    /// Read the key and value of a record whose header has already been read.
+    pub(crate) fn deserialize_after_header<T: Read>(
+        header: DiskLogEntryHeader,
+        buf: &mut T,
+    ) -> Result<Self, BitCaskError> {
+        if !header.has_known_flags() {
+            return Err(BitCaskError::CorruptedData(format!(
+                "unknown record flags {:#04x}",
+                header.flags
+            )));
+        }
+        let DiskLogEntryHeader {
+            check_sum,
+            flags,
+            key_size,
+            value_size,
+        } = header;
         // read key
-        let mut key_buf = vec![0 as u8; key_size as usize];
+        let mut key_buf = vec![0u8; key_size as usize];
         buf.read_exact(&mut key_buf)?;
         let key = key_buf;
         // ready value, if tombstone, then value is None
         let value = if value_size > 0 {
-            let mut value_buf = vec![0 as u8; value_size as usize];
+            let mut value_buf = vec![0u8; value_size as usize];
             buf.read_exact(&mut value_buf)?;
             Some(value_buf)
         } else {
@@ -134,6 +298,7 @@ impl Deserialize for DiskLogEntry {
         // construct DiskLogEntry
         let entry = Self {
             check_sum,
+            flags,
             key,
             value,
         };
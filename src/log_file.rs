@@ -1,10 +1,13 @@
 use crate::bitcask::FileId;
 use crate::error::BitCaskError;
-use crate::log_entry::{Deserialize, DiskLogEntry, Serialize};
-use crate::memory_index::{MemIndex, MemIndexEntry};
-use std::io::{BufReader, Seek, SeekFrom, Write};
-use std::path::PathBuf;
-use tracing::trace;
+use crate::log_entry::{Deserialize, DiskLogEntry, DiskLogEntryHeader, DiskLogEntryRef, Serialize};
+use crate::memory_index::{Index, MemIndexEntry, Tombstones};
+use crate::repair::plausible_record;
+use crate::stream::copy_with_checksum;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::field::Empty;
+use tracing::{instrument, trace, Span};
 
 pub(crate) struct DiskLogFile {
     pub(crate) file_id: FileId,
@@ -36,58 +39,224 @@ impl DiskLogFile {
     }
 
     // open an existing file for reading
+    #[instrument(
+        name = "recover_file",
+        level = "debug",
+        skip_all,
+        fields(file_id = file_id, bytes = Empty, records = Empty),
+    )]
     pub(crate) fn open(
         file_id: FileId,
         path: PathBuf,
-        mem_index: &mut MemIndex,
+        mem_index: &mut dyn Index,
+        tombstones: &mut Tombstones,
     ) -> Result<Self, BitCaskError> {
+        let file = Self::open_without_replay(file_id, path)?;
+        let records = file.populate_mem_index(mem_index, tombstones)?;
+        Span::current()
+            .record("bytes", file.file.metadata()?.len())
+            .record("records", records);
+        Ok(file)
+    }
+
+    /// Open an existing file whose records are already in the index.
+    pub(crate) fn open_without_replay(file_id: FileId, path: PathBuf) -> Result<Self, BitCaskError> {
         // Here all the files are opened in append mode, but we don't actually append anything except the last one
         trace!("opening disk log file: {:?}", path);
         let file = std::fs::OpenOptions::new()
             .read(true)
             .append(true)
             .open(&path)?;
-        let file = Self {
+        Ok(Self {
             file_id,
             path,
             file,
+        })
+    }
+
+    /// Cut off the last record of the file at `path` if it was only partly written, like when the process stopped in
+    /// the middle of an append. Only a record that runs to the end of the file is cut: its header and key are whole,
+    /// and either its value goes past the end of the file, or it ends exactly there as a streamed value whose checksum
+    /// was never written over the zero placeholder. A value going past the end must not hide a valid record either,
+    /// which would mean that its size is garbled rather than torn. Any other damage fails with `CorruptedData`, and is
+    /// left for repair. Returns the offset the file was cut at and the number of bytes cut off.
+    pub(crate) fn truncate_torn_tail(path: &Path) -> Result<Option<(u64, u64)>, BitCaskError> {
+        let corrupted = |offset: u64, reason: &str| {
+            BitCaskError::CorruptedData(format!("record at offset {} of {:?} {}", offset, path, reason))
         };
-        file.populate_mem_index(mem_index)?;
-        Ok(file)
+        let file = std::fs::File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut buffered_reader = BufReader::new(&file);
+        let mut cursor = 0u64;
+        let mut last_record = None;
+        let mut torn = None;
+        while cursor < file_size {
+            let remaining = file_size - cursor;
+            if remaining < DiskLogEntry::header_byte_size() {
+                return Err(corrupted(cursor, "ends within its header"));
+            }
+            let header = DiskLogEntryHeader::deserialize(&mut buffered_reader)?;
+            if !header.has_known_flags() {
+                return Err(corrupted(cursor, "has unknown flags"));
+            }
+            if header.total_byte_size() > remaining {
+                if DiskLogEntry::header_byte_size() + header.key_size > remaining {
+                    return Err(corrupted(cursor, "ends within its key"));
+                }
+                torn = Some((cursor, header));
+                break;
+            }
+            buffered_reader.seek_relative((header.key_size + header.value_size) as i64)?;
+            last_record = Some((cursor, header));
+            cursor += header.total_byte_size();
+        }
+        let cut = match (torn, last_record) {
+            (Some((offset, header)), _) => {
+                let mut rest = Vec::new();
+                buffered_reader.seek(SeekFrom::Start(
+                    offset + DiskLogEntry::header_byte_size() + header.key_size,
+                ))?;
+                buffered_reader.read_to_end(&mut rest)?;
+                // tombstones are too easy to find in anything, zeros included, to count
+                let hides_record = (0..rest.len())
+                    .any(|start| plausible_record(&rest, start).is_some_and(|(_, _, value)| !value.is_empty()));
+                if hides_record {
+                    return Err(corrupted(offset, "exceeds the file size but is followed by valid records"));
+                }
+                offset
+            }
+            (None, Some((offset, header))) if header.check_sum == 0 && header.value_size > 0 => {
+                let mut value = vec![0u8; header.value_size as usize];
+                buffered_reader.seek(SeekFrom::Start(
+                    offset + DiskLogEntry::header_byte_size() + header.key_size,
+                ))?;
+                buffered_reader.read_exact(&mut value)?;
+                if header.is_valid_for(&value) {
+                    return Ok(None);
+                }
+                offset
+            }
+            _ => return Ok(None),
+        };
+        drop(buffered_reader);
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(cut)?;
+        Ok(Some((cut, file_size - cut)))
     }
 
-    fn populate_mem_index(&self, mem_index: &mut MemIndex) -> Result<(), BitCaskError> {
+    fn populate_mem_index(
+        &self,
+        mem_index: &mut dyn Index,
+        tombstones: &mut Tombstones,
+    ) -> Result<u64, BitCaskError> {
         let file_size = self.file.metadata()?.len();
         let mut buffered_reader = BufReader::new(&self.file);
         let mut cursor = 0u64;
+        let mut records = 0;
         buffered_reader.seek(SeekFrom::Start(cursor))?;
         loop {
             if cursor >= file_size {
                 break;
             }
-            let entry = DiskLogEntry::deserialize(&mut buffered_reader)?;
+            let header = DiskLogEntryHeader::deserialize(&mut buffered_reader)?;
+            // a corrupted header must not make us allocate whatever size it claims
+            if header.total_byte_size() > file_size - cursor {
+                return Err(BitCaskError::CorruptedData(format!(
+                    "record at offset {} of {:?} exceeds the file size",
+                    cursor, self.path
+                )));
+            }
+            let entry = DiskLogEntry::deserialize_after_header(header, &mut buffered_reader)?;
             let entry_size = entry.total_byte_size();
             if entry.is_tombstone() {
                 // if it is a tombstone, we don't need to store it in mem_index
                 mem_index.delete(&entry.key);
+                tombstones.insert(entry.key);
             } else {
                 let mem_log_entry = MemIndexEntry {
                     file_id: self.file_id,
                     value_offset: cursor + entry.value_byte_offset(),
                     value_size: entry.value_byte_size(),
+                    check_sum: entry.check_sum,
+                    flags: entry.flags,
                 };
+                tombstones.remove(&entry.key);
                 mem_index.put(entry.key, mem_log_entry);
             }
             cursor += entry_size;
+            records += 1;
         }
-        Ok(())
+        Ok(records)
     }
 
-    pub(crate) fn append_new_entry(&mut self, entry: DiskLogEntry) -> Result<u64, BitCaskError> {
+    pub(crate) fn append_new_entry(&mut self, entry: DiskLogEntryRef<'_>) -> Result<u64, BitCaskError> {
         let file = &mut self.file;
         let value_offset = file.seek(SeekFrom::End(0))? + entry.value_byte_offset();
         entry.serialize(file)?;
         file.flush()?; // ensure persistency
         Ok(value_offset)
     }
+
+    /// Append a record whose value of `len` bytes is streamed from `reader`, and return its value offset and checksum.
+    /// If the reader fails or ends early, the partial record is cut off.
+    pub(crate) fn append_from_reader<R: Read>(
+        &mut self,
+        key: &[u8],
+        reader: R,
+        len: u64,
+    ) -> Result<(u64, u32), BitCaskError> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let result = self.write_streamed_entry(offset, key, reader, len);
+        if result.is_err() {
+            self.file.set_len(offset)?;
+        }
+        result
+    }
+
+    fn write_streamed_entry<R: Read>(
+        &self,
+        offset: u64,
+        key: &[u8],
+        reader: R,
+        len: u64,
+    ) -> Result<(u64, u32), BitCaskError> {
+        let header = DiskLogEntryHeader {
+            check_sum: 0,
+            flags: 0,
+            key_size: key.len() as u64,
+            value_size: len,
+        };
+        let mut writer = BufWriter::new(&self.file);
+        header.serialize(&mut writer)?;
+        writer.write_all(key)?;
+        let check_sum = copy_with_checksum(reader, &mut writer, len)?;
+        writer.flush()?;
+        // the checksum comes first in the record, so it is written over the header once the whole value is read.
+        // The file is open for appending, which would move positional writes to the end on some platforms.
+        let mut file = std::fs::OpenOptions::new().write(true).open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&check_sum.to_be_bytes())?;
+        Ok((offset + DiskLogEntry::header_byte_size() + key.len() as u64, check_sum))
+    }
+
+    /// Append all the entries with a single write, and return the value offset of each of them.
+    pub(crate) fn append_new_entries(
+        &mut self,
+        entries: &[DiskLogEntryRef<'_>],
+    ) -> Result<Vec<u64>, BitCaskError> {
+        let file = &mut self.file;
+        let mut offset = file.seek(SeekFrom::End(0))?;
+        let mut value_offsets = Vec::with_capacity(entries.len());
+        let mut buf = Vec::with_capacity(entries.iter().map(|e| e.total_byte_size() as usize).sum());
+        for entry in entries {
+            value_offsets.push(offset + entry.value_byte_offset());
+            offset += entry.total_byte_size();
+            entry.serialize(&mut buf)?;
+        }
+        file.write_all(&buf)?;
+        file.flush()?; // ensure persistency
+        Ok(value_offsets)
+    }
 }
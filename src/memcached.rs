@@ -0,0 +1,535 @@
+use crate::bitcask::{BitCask, KVStorage, PutOption, Value};
+use crate::error::BitCaskError;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, trace};
+
+/// Expiration times larger than this many seconds are absolute unix timestamps, smaller ones are relative.
+const RELATIVE_EXPTIME_LIMIT: i64 = 60 * 60 * 24 * 30;
+const MAX_KEY_LEN: usize = 250;
+const DEFAULT_MAX_VALUE_SIZE: usize = 1024 * 1024; // 1MB
+
+/// An item as it is persisted in bitcask.
+///
+/// Disk layout of the value
+///  - Flags (4 bytes long)
+///  - Expiration as unix timestamp in seconds, 0 means never (8 bytes long)
+///  - CAS unique (8 bytes long)
+///  - Data
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Item {
+    flags: u32,
+    exptime: u64,
+    cas: u64,
+    data: Vec<u8>,
+}
+
+impl Item {
+    const HEADER_LEN: usize = 4 + 8 + 8;
+
+    fn encode(&self) -> Value {
+        let mut buf = Vec::with_capacity(Self::HEADER_LEN + self.data.len());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&self.exptime.to_be_bytes());
+        buf.extend_from_slice(&self.cas.to_be_bytes());
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, BitCaskError> {
+        if buf.len() < Self::HEADER_LEN {
+            return Err(BitCaskError::CorruptedData(
+                "memcached item is too short".to_string(),
+            ));
+        }
+        Ok(Self {
+            flags: u32::from_be_bytes(buf[0..4].try_into().unwrap()),
+            exptime: u64::from_be_bytes(buf[4..12].try_into().unwrap()),
+            cas: u64::from_be_bytes(buf[12..20].try_into().unwrap()),
+            data: buf[Self::HEADER_LEN..].to_vec(),
+        })
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.exptime != 0 && self.exptime <= now
+    }
+}
+
+#[derive(Default)]
+struct ServerStats {
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    cmd_get: AtomicU64,
+    cmd_set: AtomicU64,
+    get_hits: AtomicU64,
+    get_misses: AtomicU64,
+    delete_hits: AtomicU64,
+    delete_misses: AtomicU64,
+    incr_hits: AtomicU64,
+    incr_misses: AtomicU64,
+    decr_hits: AtomicU64,
+    decr_misses: AtomicU64,
+    cas_hits: AtomicU64,
+    cas_misses: AtomicU64,
+    cas_badval: AtomicU64,
+}
+
+/// A memcached text protocol frontend on top of [`BitCask`].
+///
+/// Every accepted connection is served by its own thread. Commands that read and then modify an item
+/// (`add`, `replace`, `cas`, `incr`, `decr`, `delete`) are serialized by the server, so they are atomic with respect
+/// to other memcached clients, but not with respect to writers that use the `BitCask` handle directly.
+#[derive(Clone)]
+pub struct MemcachedServer {
+    bitcask: BitCask,
+    write_lock: Arc<Mutex<()>>,
+    next_cas: Arc<AtomicU64>,
+    stats: Arc<ServerStats>,
+    started_at: u64,
+    max_value_size: usize,
+}
+
+impl MemcachedServer {
+    pub fn new(bitcask: BitCask) -> Self {
+        let started_at = now();
+        // seed the cas counter with the current time so that cas values are not reused across restarts
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(1);
+        Self {
+            bitcask,
+            write_lock: Arc::new(Mutex::new(())),
+            next_cas: Arc::new(AtomicU64::new(seed)),
+            stats: Arc::new(ServerStats::default()),
+            started_at,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+        }
+    }
+
+    /// Set the largest data block that is accepted by storage commands, 1MB by default.
+    pub fn with_max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = max_value_size;
+        self
+    }
+
+    /// Bind to `addr` and serve connections forever.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> Result<(), BitCaskError> {
+        let listener = TcpListener::bind(addr)?;
+        self.serve(listener)
+    }
+
+    /// Serve connections accepted by `listener` forever, one thread per connection.
+    pub fn serve(self, listener: TcpListener) -> Result<(), BitCaskError> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                trace!("accepted memcached connection from {:?}", peer);
+                if let Err(e) = server.handle_connection(stream) {
+                    error!(
+                        "Error while serving memcached connection {:?}: {:?}",
+                        peer, e
+                    );
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> Result<(), BitCaskError> {
+        self.stats.curr_connections.fetch_add(1, Ordering::Relaxed);
+        self.stats.total_connections.fetch_add(1, Ordering::Relaxed);
+        let reader = BufReader::new(stream.try_clone()?);
+        let res = self.handle(reader, stream);
+        self.stats.curr_connections.fetch_sub(1, Ordering::Relaxed);
+        res
+    }
+
+    /// Run the request/response loop until the client quits or closes the connection.
+    fn handle<R: BufRead, W: Write>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+    ) -> Result<(), BitCaskError> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+            let command = match std::str::from_utf8(&line) {
+                Ok(command) => command.trim_end_matches(['\r', '\n']),
+                Err(_) => {
+                    writer.write_all(b"CLIENT_ERROR command is not valid utf-8\r\n")?;
+                    continue;
+                }
+            };
+            let tokens: Vec<&str> = command.split_ascii_whitespace().collect();
+            let response = match tokens.first() {
+                None => b"ERROR\r\n".to_vec(),
+                Some(&"quit") => return Ok(()),
+                Some(_) => self.dispatch(&tokens, &mut reader)?,
+            };
+            writer.write_all(&response)?;
+            writer.flush()?;
+        }
+    }
+
+    fn dispatch<R: BufRead>(
+        &self,
+        tokens: &[&str],
+        reader: &mut R,
+    ) -> Result<Vec<u8>, BitCaskError> {
+        let res = match tokens[0] {
+            "get" => self.get(&tokens[1..], false),
+            "gets" => self.get(&tokens[1..], true),
+            "set" | "add" | "replace" | "cas" => return self.store(tokens, reader),
+            "delete" => self.delete(&tokens[1..]),
+            "incr" => self.incr_decr(&tokens[1..], true),
+            "decr" => self.incr_decr(&tokens[1..], false),
+            "stats" => self.stats(),
+            "version" => Ok(format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes()),
+            _ => Ok(b"ERROR\r\n".to_vec()),
+        };
+        Ok(res.unwrap_or_else(|e| {
+            error!(
+                "Error while executing memcached command {:?}: {:?}",
+                tokens[0], e
+            );
+            self.recover_if_poisoned(&e);
+            format!("SERVER_ERROR {}\r\n", e).into_bytes()
+        }))
+    }
+
+    /// A panic while serving one connection poisons the storage for all of them, so it is recovered rather than failing
+    /// every command from then on.
+    fn recover_if_poisoned(&self, e: &BitCaskError) {
+        if matches!(e, BitCaskError::Poisoned) {
+            if let Err(e) = self.bitcask.recover() {
+                error!("Error while recovering the storage: {:?}", e);
+            }
+        }
+    }
+
+    fn get(&self, keys: &[&str], with_cas: bool) -> Result<Vec<u8>, BitCaskError> {
+        if keys.is_empty() {
+            return Ok(b"ERROR\r\n".to_vec());
+        }
+        let mut response = Vec::new();
+        for key in keys {
+            self.stats.cmd_get.fetch_add(1, Ordering::Relaxed);
+            let item = match self.load(key.as_bytes())? {
+                Some(item) => item,
+                None => {
+                    self.stats.get_misses.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            self.stats.get_hits.fetch_add(1, Ordering::Relaxed);
+            let header = if with_cas {
+                format!(
+                    "VALUE {} {} {} {}\r\n",
+                    key,
+                    item.flags,
+                    item.data.len(),
+                    item.cas
+                )
+            } else {
+                format!("VALUE {} {} {}\r\n", key, item.flags, item.data.len())
+            };
+            response.extend_from_slice(header.as_bytes());
+            response.extend_from_slice(&item.data);
+            response.extend_from_slice(b"\r\n");
+        }
+        response.extend_from_slice(b"END\r\n");
+        Ok(response)
+    }
+
+    /// `<command> <key> <flags> <exptime> <bytes> [<cas unique>] [noreply]` followed by a data block.
+    fn store<R: BufRead>(&self, tokens: &[&str], reader: &mut R) -> Result<Vec<u8>, BitCaskError> {
+        let command = tokens[0];
+        let arg_count = if command == "cas" { 6 } else { 5 };
+        if tokens.len() < arg_count || tokens.len() > arg_count + 1 {
+            return Ok(b"ERROR\r\n".to_vec());
+        }
+        let noreply = tokens.len() == arg_count + 1 && tokens[arg_count] == "noreply";
+        let parsed = (
+            tokens[2].parse::<u32>(),
+            tokens[3].parse::<i64>(),
+            tokens[4].parse::<usize>(),
+        );
+        let (flags, exptime, bytes) = match parsed {
+            (Ok(flags), Ok(exptime), Ok(bytes)) => (flags, exptime, bytes),
+            _ => return Ok(b"CLIENT_ERROR bad command line format\r\n".to_vec()),
+        };
+        if bytes > self.max_value_size {
+            // swallow the data block so that the connection stays in sync
+            std::io::copy(&mut reader.take(bytes as u64 + 2), &mut std::io::sink())?;
+            return Ok(b"SERVER_ERROR object too large for cache\r\n".to_vec());
+        }
+        let mut data = vec![0u8; bytes + 2];
+        reader.read_exact(&mut data)?;
+        if !data.ends_with(b"\r\n") {
+            return Ok(b"CLIENT_ERROR bad data chunk\r\n".to_vec());
+        }
+        data.truncate(bytes);
+        let key = tokens[1];
+        if !is_valid_key(key) {
+            return Ok(b"CLIENT_ERROR bad command line format\r\n".to_vec());
+        }
+        let cas_unique = if command == "cas" {
+            match tokens[5].parse::<u64>() {
+                Ok(cas_unique) => Some(cas_unique),
+                Err(_) => return Ok(b"CLIENT_ERROR bad command line format\r\n".to_vec()),
+            }
+        } else {
+            None
+        };
+
+        self.stats.cmd_set.fetch_add(1, Ordering::Relaxed);
+        let item = Item {
+            flags,
+            exptime: absolute_exptime(exptime),
+            cas: 0,
+            data,
+        };
+        let response = match self.store_item(command, key.as_bytes(), item, cas_unique) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Error while storing memcached item: {:?}", e);
+                self.recover_if_poisoned(&e);
+                format!("SERVER_ERROR {}\r\n", e)
+            }
+        };
+        if noreply {
+            return Ok(Vec::new());
+        }
+        Ok(response.into_bytes())
+    }
+
+    fn store_item(
+        &self,
+        command: &str,
+        key: &[u8],
+        mut item: Item,
+        cas_unique: Option<u64>,
+    ) -> Result<String, BitCaskError> {
+        let _guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let key = key.to_vec();
+        let existing = self.load_for_write(&key)?;
+        item.cas = self.next_cas();
+        let mut bitcask = self.bitcask.clone();
+        let value = item.encode();
+        let response = match command {
+            "set" => {
+                bitcask.put(&key, &value)?;
+                "STORED"
+            }
+            "add" => {
+                // expired items are already removed by `load_for_write`, so nx only fails for live items
+                match bitcask.put_with_option(&key, &value, PutOption::nx()) {
+                    Ok(()) => "STORED",
+                    Err(BitCaskError::KeyExists) => "NOT_STORED",
+                    Err(e) => return Err(e),
+                }
+            }
+            "replace" => match bitcask.put_with_option(&key, &value, PutOption::xx()) {
+                Ok(()) => "STORED",
+                Err(BitCaskError::KeyNotFound) => "NOT_STORED",
+                Err(e) => return Err(e),
+            },
+            "cas" => match existing {
+                None => {
+                    self.stats.cas_misses.fetch_add(1, Ordering::Relaxed);
+                    "NOT_FOUND"
+                }
+                Some(existing) if Some(existing.cas) != cas_unique => {
+                    self.stats.cas_badval.fetch_add(1, Ordering::Relaxed);
+                    "EXISTS"
+                }
+                Some(_) => {
+                    self.stats.cas_hits.fetch_add(1, Ordering::Relaxed);
+                    bitcask.put(&key, &value)?;
+                    "STORED"
+                }
+            },
+            _ => unreachable!("not a storage command: {}", command),
+        };
+        Ok(format!("{}\r\n", response))
+    }
+
+    fn delete(&self, args: &[&str]) -> Result<Vec<u8>, BitCaskError> {
+        let noreply = args.last() == Some(&"noreply");
+        let args = if noreply {
+            &args[..args.len() - 1]
+        } else {
+            args
+        };
+        if args.len() != 1 {
+            return Ok(
+                b"CLIENT_ERROR bad command line format.  Usage: delete <key> [noreply]\r\n"
+                    .to_vec(),
+            );
+        }
+        let key = args[0].as_bytes().to_vec();
+        let _guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let response: &[u8] = match self.load_for_write(&key)? {
+            Some(_) => {
+                self.bitcask.clone().delete(&key)?;
+                self.stats.delete_hits.fetch_add(1, Ordering::Relaxed);
+                b"DELETED\r\n"
+            }
+            None => {
+                self.stats.delete_misses.fetch_add(1, Ordering::Relaxed);
+                b"NOT_FOUND\r\n"
+            }
+        };
+        if noreply {
+            return Ok(Vec::new());
+        }
+        Ok(response.to_vec())
+    }
+
+    fn incr_decr(&self, args: &[&str], incr: bool) -> Result<Vec<u8>, BitCaskError> {
+        let noreply = args.len() == 3 && args[2] == "noreply";
+        if args.len() != 2 && !noreply {
+            return Ok(b"ERROR\r\n".to_vec());
+        }
+        let delta = match args[1].parse::<u64>() {
+            Ok(delta) => delta,
+            Err(_) => return Ok(b"CLIENT_ERROR invalid numeric delta argument\r\n".to_vec()),
+        };
+        let key = args[0].as_bytes().to_vec();
+        let (hits, misses) = if incr {
+            (&self.stats.incr_hits, &self.stats.incr_misses)
+        } else {
+            (&self.stats.decr_hits, &self.stats.decr_misses)
+        };
+        let _guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let response = match self.load_for_write(&key)? {
+            None => {
+                misses.fetch_add(1, Ordering::Relaxed);
+                "NOT_FOUND\r\n".to_string()
+            }
+            Some(mut item) => {
+                let current = std::str::from_utf8(&item.data)
+                    .ok()
+                    .and_then(|data| data.trim().parse::<u64>().ok());
+                match current {
+                    None => "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n"
+                        .to_string(),
+                    Some(current) => {
+                        hits.fetch_add(1, Ordering::Relaxed);
+                        // incr wraps around at 64 bits, decr stops at 0
+                        let new_value = if incr {
+                            current.wrapping_add(delta)
+                        } else {
+                            current.saturating_sub(delta)
+                        };
+                        item.data = new_value.to_string().into_bytes();
+                        item.cas = self.next_cas();
+                        self.bitcask.clone().put(&key, &item.encode())?;
+                        format!("{}\r\n", new_value)
+                    }
+                }
+            }
+        };
+        if noreply {
+            return Ok(Vec::new());
+        }
+        Ok(response.into_bytes())
+    }
+
+    fn stats(&self) -> Result<Vec<u8>, BitCaskError> {
+        let now = now();
+        let stats = &self.stats;
+        let counters = [
+            ("curr_connections", &stats.curr_connections),
+            ("total_connections", &stats.total_connections),
+            ("cmd_get", &stats.cmd_get),
+            ("cmd_set", &stats.cmd_set),
+            ("get_hits", &stats.get_hits),
+            ("get_misses", &stats.get_misses),
+            ("delete_hits", &stats.delete_hits),
+            ("delete_misses", &stats.delete_misses),
+            ("incr_hits", &stats.incr_hits),
+            ("incr_misses", &stats.incr_misses),
+            ("decr_hits", &stats.decr_hits),
+            ("decr_misses", &stats.decr_misses),
+            ("cas_hits", &stats.cas_hits),
+            ("cas_misses", &stats.cas_misses),
+            ("cas_badval", &stats.cas_badval),
+        ];
+        let mut response = String::new();
+        response.push_str(&format!("STAT pid {}\r\n", std::process::id()));
+        response.push_str(&format!("STAT uptime {}\r\n", now - self.started_at));
+        response.push_str(&format!("STAT time {}\r\n", now));
+        response.push_str(&format!("STAT version {}\r\n", env!("CARGO_PKG_VERSION")));
+        response.push_str(&format!("STAT curr_items {}\r\n", self.bitcask.size()));
+        for (name, counter) in counters {
+            response.push_str(&format!(
+                "STAT {} {}\r\n",
+                name,
+                counter.load(Ordering::Relaxed)
+            ));
+        }
+        response.push_str("END\r\n");
+        Ok(response.into_bytes())
+    }
+
+    /// Load a live item. Expired items are only skipped here, as deleting them without `write_lock` could delete a
+    /// concurrent store.
+    fn load(&self, key: &[u8]) -> Result<Option<Item>, BitCaskError> {
+        Ok(self.load_any(key)?.filter(|item| !item.is_expired(now())))
+    }
+
+    /// Load a live item for a command holding `write_lock`, expired items are deleted lazily.
+    fn load_for_write(&self, key: &[u8]) -> Result<Option<Item>, BitCaskError> {
+        match self.load_any(key)? {
+            Some(item) if item.is_expired(now()) => {
+                self.bitcask.clone().delete(key)?;
+                Ok(None)
+            }
+            item => Ok(item),
+        }
+    }
+
+    /// Load an item, expired or not.
+    fn load_any(&self, key: &[u8]) -> Result<Option<Item>, BitCaskError> {
+        match self.bitcask.try_get(key)? {
+            Some(value) => Ok(Some(Item::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn next_cas(&self) -> u64 {
+        self.next_cas.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Convert a memcached expiration time to an absolute unix timestamp, 0 means never.
+fn absolute_exptime(exptime: i64) -> u64 {
+    match exptime {
+        0 => 0,
+        // negative expiration times expire the item immediately
+        e if e < 0 => 1,
+        e if e <= RELATIVE_EXPTIME_LIMIT => now() + e as u64,
+        e => e as u64,
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && !key.bytes().any(|b| b.is_ascii_control())
+}
@@ -1,62 +1,156 @@
-use crate::bitcask::{ByteOffset, ByteSize, FileId, Key};
-use std::collections::btree_map::{BTreeMap, IntoIter};
+use crate::bitcask::{ByteOffset, ByteSize, FileId, IndexType, Key};
+use crate::compact_index::CompactIndex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct MemIndexEntry {
     pub(crate) file_id: FileId,
     pub(crate) value_offset: ByteOffset,
     pub(crate) value_size: ByteSize,
+    /// Checksum of the value as written in the record header, so that reads can be verified without the header.
+    pub(crate) check_sum: u32,
+    /// Flags of the record, which tell how the value is compressed.
+    pub(crate) flags: u8,
 }
 
+/// Keys whose latest record is a tombstone. They are not in the index, but their tombstones take space in the log
+/// until compaction drops them.
+pub(crate) type Tombstones = HashSet<Key>;
+
+/// Keys are borrowed from indexes that store them as is, and decoded by the ones that don't.
+pub(crate) type IndexIter<'a> = Box<dyn Iterator<Item = (Cow<'a, [u8]>, MemIndexEntry)> + 'a>;
+
+/// The in-memory index from every key to the location of its latest value on disk.
+///
+/// Iteration is always in key order, whatever the implementation keeps internally. Entries are returned by value, so
+/// that an implementation is free to store them packed.
+pub(crate) trait Index: Send + Sync {
+    fn get(&self, key: &[u8]) -> Option<MemIndexEntry>;
+    fn put(&mut self, key: Key, entry: MemIndexEntry) -> Option<MemIndexEntry>;
+    fn delete(&mut self, key: &[u8]) -> Option<MemIndexEntry>;
+    fn size(&self) -> usize;
+    fn iter(&self) -> IndexIter<'_>;
+    fn range(&self, range: (Bound<Key>, Bound<Key>)) -> IndexIter<'_>;
+    /// Approximate number of bytes of memory taken by the index.
+    fn heap_bytes(&self) -> usize;
+
+    /// Iterate over the entries whose key starts with `prefix`, in key order.
+    fn prefix<'a>(&'a self, prefix: &'a [u8]) -> IndexIter<'a> {
+        Box::new(
+            self.range((Bound::Included(prefix.to_vec()), Bound::Unbounded))
+                .take_while(move |(key, _)| key.starts_with(prefix)),
+        )
+    }
+}
+
+/// Create an empty index of the given type.
+pub(crate) fn new_index(index_type: IndexType) -> Box<dyn Index> {
+    match index_type {
+        IndexType::BTree => Box::new(BTreeIndex::new()),
+        IndexType::Hash => Box::new(HashIndex::new()),
+        IndexType::Compact => Box::new(CompactIndex::new()),
+    }
+}
+
+/// An index kept in key order, so that range and prefix scans only visit the keys they return.
 #[derive(Debug, Clone)]
-pub(crate) struct MemIndex {
+pub(crate) struct BTreeIndex {
     map: BTreeMap<Key, MemIndexEntry>,
 }
 
-impl MemIndex {
+impl BTreeIndex {
     pub(crate) fn new() -> Self {
         Self {
             map: BTreeMap::new(),
         }
     }
-    pub(crate) fn get(&self, key: &Key) -> Option<&MemIndexEntry> {
-        self.map.get(key)
+}
+
+impl Index for BTreeIndex {
+    fn get(&self, key: &[u8]) -> Option<MemIndexEntry> {
+        self.map.get(key).copied()
     }
-    pub(crate) fn put(&mut self, key: Key, entry: MemIndexEntry) -> Option<MemIndexEntry> {
+    fn put(&mut self, key: Key, entry: MemIndexEntry) -> Option<MemIndexEntry> {
         self.map.insert(key, entry)
     }
-    pub(crate) fn delete(&mut self, key: &Key) -> Option<MemIndexEntry> {
+    fn delete(&mut self, key: &[u8]) -> Option<MemIndexEntry> {
         self.map.remove(key)
     }
-    pub(crate) fn size(&self) -> usize {
+    fn size(&self) -> usize {
         self.map.len()
     }
+    fn iter(&self) -> IndexIter<'_> {
+        Box::new(self.map.iter().map(borrowed))
+    }
+    fn range(&self, range: (Bound<Key>, Bound<Key>)) -> IndexIter<'_> {
+        Box::new(self.map.range(range).map(borrowed))
+    }
+    fn heap_bytes(&self) -> usize {
+        // a B-tree node holds up to 11 entries and is usually two thirds full
+        let entry_bytes = size_of::<Key>() + size_of::<MemIndexEntry>();
+        self.map.keys().map(|key| key.capacity()).sum::<usize>() + self.map.len() * entry_bytes * 3 / 2
+    }
 }
 
-pub(crate) struct MemIndexIterator {
-    inner: IntoIter<Key, MemIndexEntry>,
+/// An index with faster point lookups. Ordered iteration sorts the matching keys first, so scans cost
+/// `O(n log n)` in the number of keys.
+#[derive(Debug, Clone)]
+pub(crate) struct HashIndex {
+    map: HashMap<Key, MemIndexEntry>,
 }
 
-impl IntoIterator for MemIndex {
-    type Item = (Key, MemIndexEntry);
-    type IntoIter = MemIndexIterator;
+impl HashIndex {
+    pub(crate) fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        let inner = self.map.into_iter();
-        MemIndexIterator { inner }
+impl Index for HashIndex {
+    fn get(&self, key: &[u8]) -> Option<MemIndexEntry> {
+        self.map.get(key).copied()
+    }
+    fn put(&mut self, key: Key, entry: MemIndexEntry) -> Option<MemIndexEntry> {
+        self.map.insert(key, entry)
+    }
+    fn delete(&mut self, key: &[u8]) -> Option<MemIndexEntry> {
+        self.map.remove(key)
+    }
+    fn size(&self) -> usize {
+        self.map.len()
+    }
+    fn iter(&self) -> IndexIter<'_> {
+        sorted(self.map.iter())
+    }
+    fn range(&self, range: (Bound<Key>, Bound<Key>)) -> IndexIter<'_> {
+        sorted(self.map.iter().filter(|(key, _)| range.contains(*key)))
+    }
+    fn prefix<'a>(&'a self, prefix: &'a [u8]) -> IndexIter<'a> {
+        sorted(self.map.iter().filter(|(key, _)| key.starts_with(prefix)))
+    }
+    fn heap_bytes(&self) -> usize {
+        // one control byte per bucket on top of the entries
+        let entry_bytes = size_of::<Key>() + size_of::<MemIndexEntry>() + 1;
+        self.map.keys().map(|key| key.capacity()).sum::<usize>() + self.map.capacity() * entry_bytes
     }
 }
 
-impl Iterator for MemIndexIterator {
-    type Item = (Key, MemIndexEntry);
+fn borrowed<'a>((key, entry): (&'a Key, &'a MemIndexEntry)) -> (Cow<'a, [u8]>, MemIndexEntry) {
+    (Cow::Borrowed(key), *entry)
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
-    }
+fn sorted<'a>(entries: impl Iterator<Item = (&'a Key, &'a MemIndexEntry)>) -> IndexIter<'a> {
+    let mut entries: Vec<_> = entries.collect();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+    Box::new(entries.into_iter().map(borrowed))
 }
 
 impl MemIndexEntry {
     pub(crate) fn is_tombstone(&self) -> bool {
         self.value_size == 0
     }
-}
\ No newline at end of file
+}
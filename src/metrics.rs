@@ -0,0 +1,196 @@
+use crate::bitcask::OpStats;
+#[cfg(feature = "prometheus")]
+use crate::bitcask::Stats;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counters of the work done since the data directory was opened. They are shared between the storage and its disk
+/// log, and survive the swap of the disk log at the end of a compaction.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    pub(crate) bytes_written: AtomicU64,
+    pub(crate) bytes_read: AtomicU64,
+    pub(crate) gets: OpCounter,
+    pub(crate) puts: OpCounter,
+    pub(crate) deletes: OpCounter,
+    pub(crate) scans: OpCounter,
+}
+
+impl Metrics {
+    pub(crate) fn add_written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record a batch of `puts` and `deletes` written together since `start`, splitting its time between the two in
+    /// proportion to their number.
+    pub(crate) fn record_writes(&self, puts: u64, deletes: u64, start: Instant) {
+        let nanos = start.elapsed().as_nanos() as u64;
+        let total = puts + deletes;
+        if total == 0 {
+            return;
+        }
+        let share = |count: u64| (u128::from(nanos) * u128::from(count) / u128::from(total)) as u64;
+        self.puts.record_nanos(puts, share(puts));
+        self.deletes.record_nanos(deletes, share(deletes));
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct OpCounter {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl OpCounter {
+    /// Record `count` operations done together in the time elapsed since `start`.
+    pub(crate) fn record(&self, count: u64, start: Instant) {
+        self.record_nanos(count, start.elapsed().as_nanos() as u64);
+    }
+
+    /// Record `count` operations done together in `nanos`. Each of them is taken to last an even share of the time, so
+    /// a batch adds its time to the total once.
+    fn record_nanos(&self, count: u64, nanos: u64) {
+        if count == 0 {
+            return;
+        }
+        self.count.fetch_add(count, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos / count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> OpStats {
+        OpStats {
+            count: self.count.load(Ordering::Relaxed),
+            total_time: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+            max_time: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl Stats {
+    /// Render the statistics in the Prometheus text exposition format, with metric names prefixed by `bitcask_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            out.push_str(&format!("# HELP bitcask_{} {}\n", name, help));
+            out.push_str(&format!("# TYPE bitcask_{} {}\n", name, kind));
+            for (labels, value) in samples {
+                out.push_str(&format!("bitcask_{}{} {}\n", name, labels, value));
+            }
+        };
+        let single = |value: u64| [(String::new(), value.to_string())];
+        metric(
+            "files",
+            "gauge",
+            "Number of log files.",
+            &single(self.file_count as u64),
+        );
+        metric(
+            "live_keys",
+            "gauge",
+            "Number of keys that have a value.",
+            &single(self.live_keys as u64),
+        );
+        metric(
+            "tombstones",
+            "gauge",
+            "Number of deleted keys whose tombstones are still in the log.",
+            &single(self.tombstones as u64),
+        );
+        metric(
+            "total_bytes",
+            "gauge",
+            "Size of all log files in bytes.",
+            &single(self.total_bytes),
+        );
+        metric(
+            "dead_bytes",
+            "gauge",
+            "Bytes of the log files taken by overwritten values and tombstones.",
+            &single(self.dead_bytes),
+        );
+        let file_bytes: Vec<_> = self
+            .file_sizes
+            .iter()
+            .map(|(file_id, size)| (format!("{{file=\"{}\"}}", file_id), size.to_string()))
+            .collect();
+        metric(
+            "file_bytes",
+            "gauge",
+            "Size of each log file in bytes.",
+            &file_bytes,
+        );
+        metric(
+            "blob_bytes",
+            "gauge",
+            "Size of all blob files in bytes.",
+            &single(self.blob_bytes),
+        );
+        metric(
+            "index_bytes",
+            "gauge",
+            "Approximate memory taken by the in-memory index.",
+            &single(self.index_bytes),
+        );
+        metric(
+            "cache_hits_total",
+            "counter",
+            "Point lookups served from the read cache.",
+            &single(self.cache_hits),
+        );
+        metric(
+            "cache_misses_total",
+            "counter",
+            "Point lookups of existing keys that read the disk.",
+            &single(self.cache_misses),
+        );
+        metric(
+            "written_bytes_total",
+            "counter",
+            "Bytes appended to log and blob files.",
+            &single(self.bytes_written),
+        );
+        metric(
+            "read_bytes_total",
+            "counter",
+            "Bytes read from log and blob files.",
+            &single(self.bytes_read),
+        );
+        let ops = [
+            ("get", &self.gets),
+            ("put", &self.puts),
+            ("delete", &self.deletes),
+            ("scan", &self.scans),
+        ];
+        let samples = |value: &dyn Fn(&OpStats) -> String| -> Vec<(String, String)> {
+            ops.iter()
+                .map(|(op, stats)| (format!("{{op=\"{}\"}}", op), value(stats)))
+                .collect()
+        };
+        metric(
+            "operations_total",
+            "counter",
+            "Number of operations.",
+            &samples(&|stats| stats.count.to_string()),
+        );
+        metric(
+            "operation_seconds_total",
+            "counter",
+            "Total time spent in operations.",
+            &samples(&|stats| stats.total_time.as_secs_f64().to_string()),
+        );
+        metric(
+            "operation_max_seconds",
+            "gauge",
+            "Longest time spent in a single operation.",
+            &samples(&|stats| stats.max_time.as_secs_f64().to_string()),
+        );
+        out
+    }
+}
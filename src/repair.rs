@@ -0,0 +1,207 @@
+use crate::bitcask::FileId;
+use crate::blob_file::BlobFile;
+use crate::error::BitCaskError;
+use crate::log_entry::{DiskLogEntry, DiskLogEntryHeader};
+use crate::log_file::DiskLogFile;
+use anyhow::anyhow;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use tracing::trace;
+
+/// A range of bytes `[start, end)` of a log file that could not be recovered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LostRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// What [`repair`] recovered from a single log file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileRepair {
+    pub file_id: FileId,
+    pub path: String,
+    pub size: u64,
+    /// Number of records written to the destination, including tombstones.
+    pub records: u64,
+    pub recovered_bytes: u64,
+    pub lost_ranges: Vec<LostRange>,
+}
+
+/// The result of [`repair`], serializable as JSON with [`RepairReport::to_json`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepairReport {
+    pub src_dir: String,
+    pub dest_dir: String,
+    pub files: Vec<FileRepair>,
+    /// Files that were not recovered because another file has the same id.
+    pub skipped_files: Vec<String>,
+    /// Blob files linked or copied to the destination as they are, for the recovered records to point into.
+    pub blob_files: Vec<String>,
+    pub lost_bytes: u64,
+}
+
+impl RepairReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Salvage every valid record of the log files in `src_dir` and write them to `dest_dir`, which must not contain any
+/// log or blob file yet. The blob files are linked or copied to `dest_dir` as they are. `src_dir` is not modified.
+///
+/// Unlike opening a data directory, which gives up at the first bad record, repair resynchronises past corrupted
+/// bytes by scanning forward for the next plausible record: a header whose sizes fit in the file and whose checksum
+/// matches the value. Recovered records keep their file id and order, so later writes still win over earlier ones.
+/// Each log file is read into memory as a whole.
+pub fn repair<P: AsRef<Path>, Q: AsRef<Path>>(
+    src_dir: P,
+    dest_dir: Q,
+) -> Result<RepairReport, BitCaskError> {
+    let src_dir = src_dir.as_ref();
+    let dest_dir = dest_dir.as_ref();
+    std::fs::create_dir_all(dest_dir)?;
+    if files_with_ext(dest_dir, DiskLogFile::EXT)?.next().is_some()
+        || files_with_ext(dest_dir, BlobFile::EXT)?.next().is_some()
+    {
+        return Err(BitCaskError::UnexpectedError(anyhow!(
+            "destination {} already contains log or blob files",
+            dest_dir.display()
+        )));
+    }
+
+    let mut files_by_id: BTreeMap<FileId, Vec<PathBuf>> = BTreeMap::new();
+    for path in files_with_ext(src_dir, DiskLogFile::EXT)? {
+        let file_id = path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .and_then(|file_stem| file_stem.parse::<FileId>().ok());
+        if let Some(file_id) = file_id {
+            files_by_id.entry(file_id).or_default().push(path);
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut skipped_files = Vec::new();
+    for (file_id, mut paths) in files_by_id {
+        paths.sort();
+        let mut paths = paths.into_iter();
+        let path = paths.next().unwrap();
+        skipped_files.extend(paths.map(|path| path.display().to_string()));
+        files.push(repair_file(file_id, &path, dest_dir)?);
+    }
+
+    // blobs are not scanned, damage to them shows when their values are read
+    let mut blob_files = Vec::new();
+    for path in files_with_ext(src_dir, BlobFile::EXT)? {
+        let dest_path = dest_dir.join(path.file_name().unwrap());
+        if std::fs::hard_link(&path, &dest_path).is_err() {
+            std::fs::copy(&path, dest_path)?;
+        }
+        blob_files.push(path.display().to_string());
+    }
+    blob_files.sort();
+
+    let lost_bytes = files
+        .iter()
+        .flat_map(|file| file.lost_ranges.iter())
+        .map(|range| range.end - range.start)
+        .sum();
+    Ok(RepairReport {
+        src_dir: src_dir.display().to_string(),
+        dest_dir: dest_dir.display().to_string(),
+        files,
+        skipped_files,
+        blob_files,
+        lost_bytes,
+    })
+}
+
+fn files_with_ext<'a>(data_dir: &Path, ext: &'a str) -> Result<impl Iterator<Item = PathBuf> + 'a, BitCaskError> {
+    Ok(std::fs::read_dir(data_dir)?
+        .filter_map(|path| path.ok().map(|path| path.path()))
+        .filter(move |path| path.is_file() && path.extension() == Some(OsStr::new(ext))))
+}
+
+fn repair_file(file_id: FileId, path: &Path, dest_dir: &Path) -> Result<FileRepair, BitCaskError> {
+    let buf = std::fs::read(path)?;
+    let mut dest_file = DiskLogFile::new(dest_dir, file_id)?;
+    let mut report = FileRepair {
+        file_id,
+        path: path.display().to_string(),
+        size: buf.len() as u64,
+        records: 0,
+        recovered_bytes: 0,
+        lost_ranges: Vec::new(),
+    };
+    let mut cursor = 0;
+    let mut lost_since = None;
+    while cursor < buf.len() {
+        // right after a bad record, only accept tombstones that are followed by another plausible record
+        let resyncing = lost_since.is_some();
+        match plausible_entry(&buf, cursor, resyncing) {
+            Some(entry) => {
+                if let Some(start) = lost_since.take() {
+                    trace!("resynchronised {:?} at offset {}", path, cursor);
+                    report.lost_ranges.push(LostRange {
+                        start: start as u64,
+                        end: cursor as u64,
+                    });
+                }
+                let entry_size = entry.total_byte_size();
+                dest_file.append_new_entry(entry.borrowed())?;
+                report.records += 1;
+                report.recovered_bytes += entry_size;
+                cursor += entry_size as usize;
+            }
+            None => {
+                lost_since.get_or_insert(cursor);
+                cursor += 1;
+            }
+        }
+    }
+    if let Some(start) = lost_since {
+        report.lost_ranges.push(LostRange {
+            start: start as u64,
+            end: buf.len() as u64,
+        });
+    }
+    Ok(report)
+}
+
+/// Parse the record at `offset` if it looks like one that was written by bitcask.
+fn plausible_entry(buf: &[u8], offset: usize, strict: bool) -> Option<DiskLogEntry> {
+    let (header, key, value) = plausible_record(buf, offset)?;
+    if value.is_empty() {
+        // tombstones have no checksum to go by
+        if !header.has_tombstone_check_sum() {
+            return None;
+        }
+        let next = offset + header.total_byte_size() as usize;
+        if strict && (key.is_empty() || (next < buf.len() && plausible_record(buf, next).is_none()))
+        {
+            return None;
+        }
+        return Some(DiskLogEntry::new_tombstone(key.to_vec()));
+    }
+    // the value is copied as stored, compressed or not
+    Some(DiskLogEntry::new_entry(key.to_vec(), value.to_vec()).with_flags(header.flags))
+}
+
+pub(crate) fn plausible_record(buf: &[u8], offset: usize) -> Option<(DiskLogEntryHeader, &[u8], &[u8])> {
+    let header_end = offset.checked_add(DiskLogEntryHeader::BYTE_SIZE)?;
+    let header = DiskLogEntryHeader::from_bytes(buf.get(offset..header_end)?.try_into().ok()?);
+    let remaining = (buf.len() - offset) as u64;
+    if header.total_byte_size() > remaining {
+        return None;
+    }
+    let key_end = header_end + header.key_size as usize;
+    let value_end = key_end + header.value_size as usize;
+    let key = &buf[header_end..key_end];
+    let value = &buf[key_end..value_end];
+    if !header.has_known_flags() || !header.is_valid_for(value) {
+        return None;
+    }
+    Some((header, key, value))
+}
@@ -0,0 +1,158 @@
+use crate::bitcask::FileId;
+use crate::error::BitCaskError;
+use crate::log_entry::checksum_digest;
+use crate::memory_index::{Index, MemIndexEntry, Tombstones};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::{trace, warn};
+
+/// A copy of the in-memory index written when the storage is closed, so that the next open can load it instead of
+/// replaying the whole log. It is only trusted while the log files are exactly as they were: the same files with the
+/// same sizes.
+///
+/// Disk layout, every number is big endian
+///  - Number of log files (8 bytes long), then the file id and the size of each of them (8 bytes long each)
+///  - Number of index entries (8 bytes long), then for each of them: the key size, the file id, the value offset and
+///    the value size (8 bytes long each), the checksum (4 bytes long), the flags (1 byte long) and the key
+///  - Number of tombstones (8 bytes long), then for each of them: the key size (8 bytes long) and the key
+///  - Checksum of everything above (4 bytes long)
+pub(crate) const FILE_NAME: &str = "index.snapshot";
+
+/// Write the snapshot of `mem_index` and `tombstones`, taken when the log files had `file_sizes`. The snapshot is
+/// written next to the previous one, then moved over it, so that a crash never leaves a partial snapshot behind.
+pub(crate) fn write(
+    data_dir: &Path,
+    file_sizes: &[(FileId, u64)],
+    mem_index: &dyn Index,
+    tombstones: &Tombstones,
+) -> Result<(), BitCaskError> {
+    let path = data_dir.join(FILE_NAME);
+    let tmp_path = path.with_extension("tmp");
+    let file = std::fs::File::create(&tmp_path)?;
+    let mut writer = BufWriter::new(&file);
+    let mut digest = checksum_digest();
+    let mut put = |bytes: &[u8]| -> Result<(), BitCaskError> {
+        digest.update(bytes);
+        writer.write_all(bytes)?;
+        Ok(())
+    };
+    put(&(file_sizes.len() as u64).to_be_bytes())?;
+    for (file_id, size) in file_sizes {
+        put(&(*file_id as u64).to_be_bytes())?;
+        put(&size.to_be_bytes())?;
+    }
+    put(&(mem_index.size() as u64).to_be_bytes())?;
+    for (key, entry) in mem_index.iter() {
+        put(&(key.len() as u64).to_be_bytes())?;
+        put(&(entry.file_id as u64).to_be_bytes())?;
+        put(&entry.value_offset.to_be_bytes())?;
+        put(&entry.value_size.to_be_bytes())?;
+        put(&entry.check_sum.to_be_bytes())?;
+        put(&[entry.flags])?;
+        put(&key)?;
+    }
+    put(&(tombstones.len() as u64).to_be_bytes())?;
+    for key in tombstones {
+        put(&(key.len() as u64).to_be_bytes())?;
+        put(key)?;
+    }
+    writer.write_all(&digest.finalize().to_be_bytes())?;
+    writer.flush()?;
+    drop(writer);
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, &path)?;
+    trace!("wrote the index snapshot {:?}", path);
+    Ok(())
+}
+
+/// Load the snapshot of `data_dir` into `mem_index` and `tombstones`, if there is one and the log files still have
+/// `file_sizes`. Returns whether it was loaded; otherwise nothing is loaded and the log has to be replayed.
+pub(crate) fn load(
+    data_dir: &Path,
+    file_sizes: &[(FileId, u64)],
+    mem_index: &mut dyn Index,
+    tombstones: &mut Tombstones,
+) -> Result<bool, BitCaskError> {
+    let path = data_dir.join(FILE_NAME);
+    let buf = match std::fs::read(&path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if buf.len() < 4 {
+        warn!("Ignoring the truncated index snapshot {:?}", path);
+        return Ok(false);
+    }
+    let (body, check_sum) = buf.split_at(buf.len() - 4);
+    let mut digest = checksum_digest();
+    digest.update(body);
+    if digest.finalize().to_be_bytes() != check_sum {
+        warn!(
+            "Ignoring the index snapshot {:?}, it does not match its checksum",
+            path
+        );
+        return Ok(false);
+    }
+    let mut reader = SnapshotReader { buf: body };
+    let file_count = reader.u64()? as usize;
+    let mut snapshot_file_sizes = Vec::with_capacity(file_count.min(file_sizes.len() + 1));
+    for _ in 0..file_count {
+        snapshot_file_sizes.push((reader.u64()? as FileId, reader.u64()?));
+    }
+    if snapshot_file_sizes != file_sizes {
+        trace!(
+            "the log files changed since the index snapshot {:?} was written",
+            path
+        );
+        return Ok(false);
+    }
+    // the whole snapshot is read before anything is loaded
+    let mut entries = Vec::new();
+    for _ in 0..reader.u64()? {
+        let key_size = reader.u64()? as usize;
+        let entry = MemIndexEntry {
+            file_id: reader.u64()? as FileId,
+            value_offset: reader.u64()?,
+            value_size: reader.u64()?,
+            check_sum: u32::from_be_bytes(reader.take(4)?.try_into().unwrap()),
+            flags: reader.take(1)?[0],
+        };
+        entries.push((reader.take(key_size)?.to_vec(), entry));
+    }
+    let mut snapshot_tombstones = Tombstones::new();
+    for _ in 0..reader.u64()? {
+        let key_size = reader.u64()? as usize;
+        snapshot_tombstones.insert(reader.take(key_size)?.to_vec());
+    }
+    if !reader.buf.is_empty() {
+        return Err(corrupted_snapshot());
+    }
+    for (key, entry) in entries {
+        mem_index.put(key, entry);
+    }
+    tombstones.extend(snapshot_tombstones);
+    Ok(true)
+}
+
+struct SnapshotReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BitCaskError> {
+        if len > self.buf.len() {
+            return Err(corrupted_snapshot());
+        }
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn u64(&mut self) -> Result<u64, BitCaskError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn corrupted_snapshot() -> BitCaskError {
+    BitCaskError::CorruptedData("the index snapshot does not match its own layout".to_string())
+}
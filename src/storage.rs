@@ -1,40 +1,143 @@
-use crate::bitcask::{Key, PutOption, Value};
+use crate::bitcask::{FileId, Key, Options, PutOption, Stats, Value};
+use crate::blob_file::BlobPointer;
+use crate::cache::ValueCache;
 use crate::disk_logs::DiskLog;
 use crate::error::BitCaskError;
-use crate::log_entry::DiskLogEntry;
+use crate::events::{Event, Events};
+use crate::export::{ExportFormat, RecordWriter};
+use crate::group_commit::{copy_error, WriteOp};
+use crate::log_entry::{DiskLogEntry, DiskLogEntryRef, FLAG_BLOB};
 use crate::log_file::DiskLogFile;
-use crate::memory_index::MemIndex;
-use std::path::PathBuf;
-use tracing::error;
+use crate::memory_index::{new_index, Index, MemIndexEntry, Tombstones};
+use crate::metrics::Metrics;
+use crate::snapshot;
+use crate::stream::{StagedValue, StagingFile, ValueReader};
+use crate::value_format::ValueFormat;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{File, TryLockError};
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
+use tracing::field::{debug, Empty};
+use tracing::{error, instrument, Span};
+
+const LOCK_FILE_NAME: &str = "LOCK";
 
 pub struct LogIndexStorage {
     data_dir: PathBuf,
     disk_log: DiskLog,
-    mem_index: MemIndex,
+    mem_index: Box<dyn Index>,
+    tombstones: Tombstones,
+    options: Options,
+    /// Reads only hold a shared lock on the storage, so the cache has its own.
+    cache: Option<Mutex<ValueCache>>,
+    metrics: Arc<Metrics>,
+    events: Arc<Events>,
+    /// The locked lock file of the data directory, with `lock_directory`.
+    lock: Option<File>,
+    closed: bool,
 }
 
 impl LogIndexStorage {
-    pub fn new<T: Into<PathBuf>>(data_dir: T) -> Result<Self, BitCaskError> {
+    #[instrument(
+        name = "recovery",
+        level = "info",
+        skip_all,
+        fields(dir = Empty, live_keys = Empty, tombstones = Empty),
+    )]
+    pub fn new<T: Into<PathBuf>>(data_dir: T, options: Options) -> Result<Self, BitCaskError> {
         let data_dir: PathBuf = data_dir.into();
+        Span::current().record("dir", debug(&data_dir));
         std::fs::create_dir_all(&data_dir)?;
-        let mut mem_index = MemIndex::new();
+        let lock = match options.lock_directory {
+            true => Some(lock_directory(&data_dir)?),
+            false => None,
+        };
+        remove_staging_files(&data_dir)?;
+        let mut mem_index = new_index(options.index);
+        let mut tombstones = Tombstones::new();
+        let events = Arc::new(Events::new(
+            options.event_listeners.clone(),
+            options.event_delivery,
+        ));
         // Populate mem_index from disk
-        let disk_log = DiskLog::from_disk(&data_dir, &mut mem_index)?;
+        let mut disk_log = DiskLog::from_disk(
+            &data_dir,
+            mem_index.as_mut(),
+            &mut tombstones,
+            options.truncate_torn_tail,
+            events.clone(),
+        )?;
+        disk_log.configure(&options);
+        let metrics = Arc::new(Metrics::default());
+        disk_log.set_metrics(metrics.clone());
+        disk_log.check_encryption_key(mem_index.as_ref())?;
+        let cache = options
+            .cache_size
+            .map(|cache_size| Mutex::new(ValueCache::new(cache_size)));
+        Span::current()
+            .record("live_keys", mem_index.size())
+            .record("tombstones", tombstones.len());
         Ok(Self {
             data_dir,
             disk_log,
             mem_index,
+            tombstones,
+            options,
+            cache,
+            metrics,
+            events,
+            lock,
+            closed: false,
         })
     }
 
+    pub(crate) fn events(&self) -> Arc<Events> {
+        self.events.clone()
+    }
+
+    pub(crate) fn check_open(&self) -> Result<(), BitCaskError> {
+        match self.closed {
+            true => Err(BitCaskError::Closed),
+            false => Ok(()),
+        }
+    }
+
+    /// Make the log durable, write the index snapshot and release the directory lock. The files stay open until the
+    /// storage is dropped, but every operation fails with `Closed` from then on.
+    #[instrument(level = "info", skip_all, fields(dir = ?self.data_dir))]
+    pub(crate) fn close(&mut self) -> Result<(), BitCaskError> {
+        self.check_open()?;
+        self.disk_log.sync_all()?;
+        snapshot::write(
+            &self.data_dir,
+            &self.disk_log.file_sizes()?,
+            self.mem_index.as_ref(),
+            &self.tombstones,
+        )?;
+        self.lock = None;
+        self.closed = true;
+        Ok(())
+    }
+
+    #[instrument(level = "info", skip_all, fields(immutable_files = Empty))]
     pub(crate) fn prepare_compaction(&mut self) -> Result<Vec<PathBuf>, BitCaskError> {
         // step 0: create a new empty log file
         self.disk_log.create_new_file()?;
         // step 1: return the immutable files and the mem_index
         let immutable_files = self.disk_log.get_immutable_files();
+        Span::current().record("immutable_files", immutable_files.len());
+        self.events.emit(Event::CompactionStarted {
+            immutable_files: immutable_files.len(),
+        });
         Ok(immutable_files)
     }
 
+    #[instrument(level = "info", skip_all, fields(dir = ?new_log_file_path, live_keys = Empty))]
     pub(crate) fn finish_compaction(
         &mut self,
         immutable_files: Vec<PathBuf>,
@@ -44,106 +147,556 @@ impl LogIndexStorage {
         self.disk_log
             .copy_files_to_new_dir(immutable_files, new_log_file_path.clone())?;
         // step 4: initialize a new DiskLog and MemIndex from the new log file
-        let mut mem_index = MemIndex::new();
-        let disk_log = DiskLog::from_disk(&new_log_file_path, &mut mem_index)?;
+        let lock = match self.options.lock_directory {
+            true => Some(lock_directory(&new_log_file_path)?),
+            false => None,
+        };
+        self.load_from_disk(new_log_file_path.clone(), self.options.truncate_torn_tail)?;
+        Span::current().record("live_keys", self.mem_index.size());
+        self.events.emit(Event::CompactionFinished {
+            dir: new_log_file_path,
+            live_keys: self.mem_index.size(),
+        });
+        // the lock of the previous directory is released
+        self.lock = lock;
+        Ok(())
+    }
+
+    /// Rebuild the index from the log, after a panic left the storage in an unknown state. A record that the panic
+    /// left half written at the end of the log is cut off whatever `Options::truncate_torn_tail` says, as this process
+    /// was the one writing it.
+    #[instrument(level = "info", skip_all, fields(dir = ?self.data_dir, live_keys = Empty))]
+    pub(crate) fn recover(&mut self) -> Result<(), BitCaskError> {
+        self.check_open()?;
+        self.load_from_disk(self.data_dir.clone(), true)?;
+        Span::current().record("live_keys", self.mem_index.size());
+        Ok(())
+    }
+
+    /// Replace the disk log and the index with the ones loaded from `data_dir`.
+    fn load_from_disk(&mut self, data_dir: PathBuf, truncate_torn_tail: bool) -> Result<(), BitCaskError> {
+        let mut mem_index = new_index(self.options.index);
+        let mut tombstones = Tombstones::new();
+        let mut disk_log = DiskLog::from_disk(
+            &data_dir,
+            mem_index.as_mut(),
+            &mut tombstones,
+            truncate_torn_tail,
+            self.events.clone(),
+        )?;
+        disk_log.configure(&self.options);
+        disk_log.set_metrics(self.metrics.clone());
         self.disk_log = disk_log;
         self.mem_index = mem_index;
-        self.data_dir = new_log_file_path.into();
+        self.tombstones = tombstones;
+        self.data_dir = data_dir;
+        // cached values may still be current, but drop them rather than trust them across the swap
+        if let Some(cache) = &mut self.cache {
+            cache.get_mut().unwrap_or_else(PoisonError::into_inner).clear();
+            cache.clear_poison();
+        }
         Ok(())
     }
 
-    pub(crate) fn get(&self, key: &Key) -> Option<Value> {
-        let mem_index_entry = self.mem_index.get(key);
-        match mem_index_entry {
-            Some(mem_index_entry) => {
-                if mem_index_entry.is_tombstone() {
-                    return None;
-                }
-                let res = self.disk_log.get(&mem_index_entry);
-                match res {
-                    Ok(value) => Some(value),
-                    Err(e) => {
-                        error!("Error while getting value from disk log: {:?}", e);
-                        None
-                    }
-                }
-            }
-            None => None,
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(key_len = key.len(), file_id = Empty, bytes = Empty, cache_hit = Empty),
+    )]
+    pub(crate) fn get(&self, key: &[u8]) -> Result<Option<Value>, BitCaskError> {
+        let start = Instant::now();
+        let result = self.get_value(key);
+        self.metrics.gets.record(1, start);
+        result
+    }
+
+    fn get_value(&self, key: &[u8]) -> Result<Option<Value>, BitCaskError> {
+        let mem_index_entry = match self.mem_index.get(key) {
+            Some(mem_index_entry) => mem_index_entry,
+            None => return Ok(None),
+        };
+        let span = Span::current();
+        span.record("file_id", mem_index_entry.file_id)
+            .record("bytes", mem_index_entry.value_size);
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Ok(Some(self.read_value(key, &mem_index_entry)?)),
+        };
+        if let Some(value) = lock_cache(cache)?.get(key) {
+            span.record("cache_hit", true);
+            return Ok(Some(value));
         }
+        span.record("cache_hit", false);
+        let value = self.read_value(key, &mem_index_entry)?;
+        lock_cache(cache)?.insert(key.to_vec(), value.clone());
+        Ok(Some(value))
     }
 
-    pub(crate) fn put(&mut self, key: &Key, value: &Value, option: Option<PutOption>) -> Result<(), BitCaskError> {
-        match option {
-            Some(option) => {
-                if option.nx {
-                    return self.put_nx(key, value);
-                }
-                if option.xx {
-                    return self.put_xx(key, value);
-                }
-                self.put_without_option(key, value)
-            }
-            None => self.put_without_option(key, value),
+    pub(crate) fn get_reader(&self, key: &[u8]) -> Result<Option<ValueReader>, BitCaskError> {
+        match self.mem_index.get(key) {
+            Some(mem_index_entry) => Ok(Some(self.events.detect_corruption(
+                self.disk_log
+                    .get_reader(key, &mem_index_entry, self.options.verify_checksums),
+            )?)),
+            None => Ok(None),
         }
     }
 
-    pub(crate) fn put_without_option(&mut self, key: &Key, value: &Value) -> Result<(), BitCaskError> {
-        let index_entry = self.disk_log.put(key, value)?;
-        self.mem_index.put(key.clone(), index_entry);
-        Ok(())
+    /// Get the values of all the keys, reading them in the order they are laid out on disk.
+    #[instrument(level = "debug", skip_all, fields(keys = keys.len()))]
+    pub(crate) fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>, BitCaskError> {
+        let start = Instant::now();
+        let result = self.read_values(keys);
+        self.metrics.gets.record(keys.len() as u64, start);
+        result
     }
 
-    pub(crate) fn put_nx(&mut self, key: &Key, value: &Value) -> Result<(), BitCaskError> {
-        let index_entry = self.mem_index.get(key);
-        if let Some(index_entry) = index_entry {
-            if !index_entry.is_tombstone() {
-                return Err(BitCaskError::KeyExists);
-            }
+    fn read_values<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>, BitCaskError> {
+        let mut reads: Vec<(usize, MemIndexEntry)> = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, key)| {
+                self.mem_index
+                    .get(key.as_ref())
+                    .map(|mem_index_entry| (i, mem_index_entry))
+            })
+            .collect();
+        reads.sort_by_key(|(_, mem_index_entry)| (mem_index_entry.file_id, mem_index_entry.value_offset));
+        let mut values = vec![None; keys.len()];
+        for (i, mem_index_entry) in reads {
+            values[i] = Some(self.read_value(keys[i].as_ref(), &mem_index_entry)?);
         }
+        Ok(values)
+    }
+
+    fn read_value(&self, key: &[u8], mem_index_entry: &MemIndexEntry) -> Result<Value, BitCaskError> {
+        let value = if self.options.verify_checksums {
+            self.disk_log.get_verified(key, mem_index_entry)
+        } else {
+            self.disk_log.get(key, mem_index_entry)
+        };
+        self.events.detect_corruption(value)
+    }
+
+    #[instrument(level = "debug", skip_all, fields(key_len = key.len(), bytes = value.len(), file_id = Empty))]
+    pub(crate) fn put(&mut self, key: &[u8], value: &[u8], option: Option<PutOption>) -> Result<(), BitCaskError> {
+        let start = Instant::now();
+        let result = match option {
+            Some(option) if option.nx => self.put_nx(key, value),
+            Some(option) if option.xx => self.put_xx(key, value),
+            _ => self.put_without_option(key, value),
+        };
+        self.metrics.puts.record(1, start);
+        result
+    }
+
+    pub(crate) fn put_without_option(&mut self, key: &[u8], value: &[u8]) -> Result<(), BitCaskError> {
         let index_entry = self.disk_log.put(key, value)?;
-        self.mem_index.put(key.clone(), index_entry);
+        Span::current().record("file_id", index_entry.file_id);
+        self.update_index(key, index_entry);
         Ok(())
     }
 
-    pub(crate) fn put_xx(&mut self, key: &Key, value: &Value) -> Result<(), BitCaskError> {
-        let index_entry = self.mem_index.get(key);
-        if let Some(index_entry) = index_entry {
-            if index_entry.is_tombstone() {
-                return Err(BitCaskError::KeyNotFound);
+    pub(crate) fn put_nx(&mut self, key: &[u8], value: &[u8]) -> Result<(), BitCaskError> {
+        if self.mem_index.get(key).is_some() {
+            return Err(BitCaskError::KeyExists);
+        }
+        self.put_without_option(key, value)
+    }
+
+    pub(crate) fn put_xx(&mut self, key: &[u8], value: &[u8]) -> Result<(), BitCaskError> {
+        if self.mem_index.get(key).is_none() {
+            return Err(BitCaskError::KeyNotFound);
+        }
+        self.put_without_option(key, value)
+    }
+
+    /// Point `key` to the record just appended for it. Tombstones, including empty values, take the key out of the
+    /// index. Every write goes through here, which keeps the cache from serving stale values.
+    fn update_index(&mut self, key: &[u8], index_entry: MemIndexEntry) {
+        if let Some(cache) = &self.cache {
+            // a poisoned cache is cleared by `recover` anyway
+            if let Ok(mut cache) = cache.lock() {
+                cache.invalidate(key);
             }
+        }
+        if index_entry.is_tombstone() {
+            self.mem_index.delete(key);
+            self.tombstones.insert(key.to_vec());
         } else {
-            return Err(BitCaskError::KeyNotFound);
+            if !self.tombstones.is_empty() {
+                self.tombstones.remove(key);
+            }
+            self.mem_index.put(key.to_vec(), index_entry);
         }
-        let index_entry = self.disk_log.put(key, value)?;
-        self.mem_index.put(key.clone(), index_entry);
+    }
+
+    fn put_staged(&mut self, key: &[u8], staged: StagedValue) -> Result<(), BitCaskError> {
+        let index_entry = self.disk_log.put_staged(key, staged)?;
+        Span::current().record("file_id", index_entry.file_id);
+        self.update_index(key, index_entry);
+        Ok(())
+    }
+
+    /// Put all the key-value pairs with a single write, without any option.
+    #[instrument(level = "debug", skip_all, fields(pairs = pairs.len(), file_id = Empty))]
+    pub(crate) fn put_batch<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, pairs: &[(K, V)]) -> Result<(), BitCaskError> {
+        let start = Instant::now();
+        let entries: Vec<DiskLogEntryRef> = pairs
+            .iter()
+            .map(|(key, value)| DiskLogEntryRef::new_entry(key.as_ref(), value.as_ref()))
+            .collect();
+        let index_entries = self.disk_log.append_batch(&entries)?;
+        if let Some(index_entry) = index_entries.first() {
+            Span::current().record("file_id", index_entry.file_id);
+        }
+        for ((key, _), index_entry) in pairs.iter().zip(index_entries) {
+            self.update_index(key.as_ref(), index_entry);
+        }
+        self.metrics.puts.record(pairs.len() as u64, start);
         Ok(())
     }
 
-    pub(crate) fn delete(&mut self, key: &Key) -> Result<(), BitCaskError> {
+    #[instrument(level = "debug", skip_all, fields(key_len = key.len(), file_id = Empty))]
+    pub(crate) fn delete(&mut self, key: &[u8]) -> Result<(), BitCaskError> {
+        let start = Instant::now();
         let index_entry = self.disk_log.delete(key)?;
-        self.mem_index.put(key.clone(), index_entry);
+        Span::current().record("file_id", index_entry.file_id);
+        self.update_index(key, index_entry);
+        self.metrics.deletes.record(1, start);
         Ok(())
     }
 
+    /// Apply the writes of a group commit in order, with a single append to the log. Puts whose option fails get
+    /// their own error and are left out of the append; if the append fails, every write of the batch fails.
+    #[instrument(level = "debug", skip_all, fields(ops = ops.len()))]
+    pub(crate) fn apply_writes(&mut self, ops: &[WriteOp]) -> Vec<Result<(), BitCaskError>> {
+        let start = Instant::now();
+        // whether each key has a value once the earlier writes of the batch are applied
+        let mut live: HashMap<&[u8], bool> = HashMap::new();
+        let mut results = Vec::with_capacity(ops.len());
+        let mut entries = Vec::with_capacity(ops.len());
+        for op in ops {
+            let key = op.key();
+            let exists = match live.get(key) {
+                Some(exists) => *exists,
+                None => self.mem_index.get(key).is_some(),
+            };
+            match op {
+                WriteOp::Put { nx: true, .. } if exists => results.push(Err(BitCaskError::KeyExists)),
+                WriteOp::Put { xx: true, .. } if !exists => results.push(Err(BitCaskError::KeyNotFound)),
+                WriteOp::Put { value, .. } => {
+                    live.insert(key, !value.is_empty());
+                    entries.push(DiskLogEntryRef::new_entry(key, value));
+                    results.push(Ok(()));
+                }
+                WriteOp::Delete { .. } => {
+                    live.insert(key, false);
+                    entries.push(DiskLogEntryRef::new_tombstone(key));
+                    results.push(Ok(()));
+                }
+            }
+        }
+        let deletes = ops.iter().filter(|op| matches!(op, WriteOp::Delete { .. })).count() as u64;
+        let result = self.disk_log.append_batch(&entries);
+        self.metrics.record_writes(ops.len() as u64 - deletes, deletes, start);
+        match result {
+            Ok(index_entries) => {
+                for (entry, index_entry) in entries.iter().zip(index_entries) {
+                    self.update_index(entry.key, index_entry);
+                }
+                results
+            }
+            Err(e) => ops.iter().map(|_| Err(copy_error(&e))).collect(),
+        }
+    }
+
     pub(crate) fn size(&self) -> usize {
         self.mem_index.size()
     }
+
+    pub(crate) fn tombstone_count(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    pub(crate) fn range(&self, range: (Bound<Key>, Bound<Key>)) -> Result<Vec<(Key, Value)>, BitCaskError> {
+        let start = Instant::now();
+        // the indexes panic on a range that starts after its end
+        if is_empty_range(&range) {
+            self.metrics.scans.record(1, start);
+            return Ok(Vec::new());
+        }
+        let result = self
+            .mem_index
+            .range(range)
+            .map(|(key, mem_index_entry)| {
+                let value = self.read_value(&key, &mem_index_entry)?;
+                Ok((key.into_owned(), value))
+            })
+            .collect();
+        self.metrics.scans.record(1, start);
+        result
+    }
+
+    pub(crate) fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Key, Value)>, BitCaskError> {
+        let start = Instant::now();
+        let result = self
+            .mem_index
+            .prefix(prefix)
+            .map(|(key, mem_index_entry)| {
+                let value = self.read_value(&key, &mem_index_entry)?;
+                Ok((key.into_owned(), value))
+            })
+            .collect();
+        self.metrics.scans.record(1, start);
+        result
+    }
+
+    /// Write all the live key-value pairs to `writer`, in key order.
+    pub(crate) fn export<W: Write>(&self, writer: W, format: ExportFormat) -> Result<usize, BitCaskError> {
+        let mut writer = RecordWriter::new(writer, format)?;
+        let mut count = 0;
+        for (key, mem_index_entry) in self.mem_index.iter() {
+            writer.write(&key, &self.read_value(&key, &mem_index_entry)?)?;
+            count += 1;
+        }
+        writer.finish()?;
+        Ok(count)
+    }
+
+    pub(crate) fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub(crate) fn stats(&self) -> Result<Stats, BitCaskError> {
+        let file_sizes = self.disk_log.file_sizes()?;
+        let (cache_hits, cache_misses) = match &self.cache {
+            Some(cache) => {
+                let cache = lock_cache(cache)?;
+                (cache.hits, cache.misses)
+            }
+            None => (0, 0),
+        };
+        let total_bytes: u64 = file_sizes.iter().map(|(_, size)| size).sum();
+        let mut live_bytes = 0;
+        for (key, mem_index_entry) in self.mem_index.iter() {
+            live_bytes +=
+                DiskLogEntry::header_byte_size() + key.len() as u64 + mem_index_entry.value_size;
+        }
+        let metrics = &self.metrics;
+        Ok(Stats {
+            file_count: file_sizes.len(),
+            live_keys: self.mem_index.size(),
+            tombstones: self.tombstones.len(),
+            total_bytes,
+            dead_bytes: total_bytes.saturating_sub(live_bytes),
+            index_bytes: self.mem_index.heap_bytes() as u64,
+            blob_bytes: self.disk_log.blob_file_sizes()?.iter().map(|(_, size)| size).sum(),
+            cache_hits,
+            cache_misses,
+            bytes_written: metrics.bytes_written.load(Ordering::Relaxed),
+            bytes_read: metrics.bytes_read.load(Ordering::Relaxed),
+            gets: metrics.gets.snapshot(),
+            puts: metrics.puts.snapshot(),
+            deletes: metrics.deletes.snapshot(),
+            scans: metrics.scans.snapshot(),
+            file_sizes,
+        })
+    }
+
+    /// Move the live values out of the blob files of which at least `min_garbage_ratio` of the bytes are dead, point
+    /// their keys to the new copies, and delete these files. Returns the number of bytes reclaimed.
+    #[instrument(level = "info", skip(self), fields(reclaimed = Empty))]
+    pub(crate) fn gc_blobs(&mut self, min_garbage_ratio: f64) -> Result<u64, BitCaskError> {
+        let mut live: HashMap<FileId, Vec<(Key, MemIndexEntry, BlobPointer)>> = HashMap::new();
+        for (key, mem_index_entry) in self.mem_index.iter() {
+            if mem_index_entry.flags & FLAG_BLOB != 0 {
+                let pointer = self.disk_log.blob_pointer(&mem_index_entry)?;
+                live.entry(pointer.file_id)
+                    .or_default()
+                    .push((key.into_owned(), mem_index_entry, pointer));
+            }
+        }
+        let mut collected = Vec::new();
+        for (file_id, size) in self.disk_log.blob_file_sizes()? {
+            let live_bytes: u64 = live
+                .get(&file_id)
+                .map_or(0, |blobs| blobs.iter().map(|(_, _, pointer)| pointer.size).sum());
+            let dead_bytes = size.saturating_sub(live_bytes);
+            if dead_bytes > 0 && dead_bytes as f64 >= min_garbage_ratio * size as f64 {
+                collected.push((file_id, dead_bytes));
+            }
+        }
+        // the live values must not be moved to a file that is about to be deleted
+        let current = self.disk_log.current_blob_file_id();
+        if collected.iter().any(|(file_id, _)| Some(*file_id) == current) {
+            self.disk_log.create_new_blob_file()?;
+        }
+        let mut reclaimed = 0;
+        for (file_id, dead_bytes) in collected {
+            let blobs = live.remove(&file_id).unwrap_or_default();
+            let pointers = blobs
+                .iter()
+                .map(|(_, _, pointer)| Ok(self.disk_log.move_blob(pointer)?.encode()))
+                .collect::<Result<Vec<_>, BitCaskError>>()?;
+            self.disk_log.sync_all()?;
+            let entries: Vec<DiskLogEntryRef> = blobs
+                .iter()
+                .zip(&pointers)
+                .map(|((key, mem_index_entry, _), pointer)| {
+                    DiskLogEntryRef::new_entry(key, pointer).with_flags(mem_index_entry.flags)
+                })
+                .collect();
+            let index_entries = self.disk_log.append_stored(&entries)?;
+            // the new pointers must be durable before the old values are gone
+            self.disk_log.sync_all()?;
+            for ((key, _, _), index_entry) in blobs.into_iter().zip(index_entries) {
+                self.mem_index.put(key, index_entry);
+            }
+            self.disk_log.delete_blob_file(file_id)?;
+            reclaimed += dead_bytes;
+        }
+        Span::current().record("reclaimed", reclaimed);
+        Ok(reclaimed)
+    }
+}
+
+/// Lock `storage` for reading, unless it is closed or a panic poisoned the lock.
+pub(crate) fn read_lock(storage: &RwLock<LogIndexStorage>) -> Result<RwLockReadGuard<'_, LogIndexStorage>, BitCaskError> {
+    let storage = storage.read().map_err(|_| BitCaskError::Poisoned)?;
+    storage.check_open()?;
+    Ok(storage)
+}
+
+/// Lock `storage` for writing, unless it is closed or a panic poisoned the lock.
+pub(crate) fn write_lock(
+    storage: &RwLock<LogIndexStorage>,
+) -> Result<RwLockWriteGuard<'_, LogIndexStorage>, BitCaskError> {
+    let storage = storage.write().map_err(|_| BitCaskError::Poisoned)?;
+    storage.check_open()?;
+    Ok(storage)
+}
+
+/// Whether `range` cannot hold any key, because it starts after its end or both of its bounds exclude the same key.
+fn is_empty_range(range: &(Bound<Key>, Bound<Key>)) -> bool {
+    match range {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Put a value of `len` bytes streamed from `reader`. The value is read before `storage` is locked for writing, to a
+/// staging file next to the log or into memory if it is compressed or encrypted anyway, so that a slow reader does not
+/// block the other operations.
+#[instrument(level = "debug", skip_all, fields(key_len = key.len(), bytes = len, file_id = Empty))]
+pub(crate) fn put_from_reader<R: Read>(
+    storage: &RwLock<LogIndexStorage>,
+    key: &[u8],
+    reader: R,
+    len: u64,
+) -> Result<(), BitCaskError> {
+    let start = Instant::now();
+    let (data_dir, stored_as_is, metrics) = {
+        let storage = read_lock(storage)?;
+        (
+            storage.data_dir.clone(),
+            storage.disk_log.stores_values_as_is(),
+            storage.metrics.clone(),
+        )
+    };
+    // timed like `put`, whether it succeeds or not
+    let result = StagedValue::read(&data_dir, stored_as_is, reader, len)
+        .and_then(|staged| write_lock(storage)?.put_staged(key, staged));
+    metrics.puts.record(1, start);
+    result
+}
+
+/// Reads only take a shared lock on the storage, which a panic does not poison, so the cache is poisoned on its own.
+fn lock_cache(cache: &Mutex<ValueCache>) -> Result<MutexGuard<'_, ValueCache>, BitCaskError> {
+    cache.lock().map_err(|_| BitCaskError::Poisoned)
+}
+
+impl Drop for LogIndexStorage {
+    /// A storage that was not closed still gets its log fsynced, but no index snapshot.
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.disk_log.sync_all() {
+            error!("Error while syncing the log on drop: {:?}", e);
+        }
+    }
+}
+
+/// Remove the staging files left behind by a process that stopped in the middle of a `put_from_reader`.
+fn remove_staging_files(data_dir: &Path) -> Result<(), BitCaskError> {
+    for entry in std::fs::read_dir(data_dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new(StagingFile::EXT)) {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
 }
 
+/// Lock the data directory for this process, so that a second instance opening it fails instead of corrupting it. The
+/// lock is released when the returned file is dropped, or the process ends.
+fn lock_directory(data_dir: &Path) -> Result<File, BitCaskError> {
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(data_dir.join(LOCK_FILE_NAME))?;
+    match lock.try_lock() {
+        Ok(()) => Ok(lock),
+        Err(TryLockError::WouldBlock) => Err(BitCaskError::IoError(std::io::Error::new(
+            ErrorKind::WouldBlock,
+            format!("the data directory {:?} is locked by another instance", data_dir),
+        ))),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Values are recompressed and reencrypted according to `options`, so compaction also rotates the encryption key.
+/// Blobs are left where they are, only the records pointing to them are copied.
+#[instrument(
+    level = "info",
+    skip_all,
+    fields(dir = ?new_log_file_path, immutable_files = immutable_files.len(), keys = Empty, bytes = Empty),
+)]
 pub(crate) fn start_compaction(
     immutable_files: Vec<PathBuf>,
     new_log_file_path: PathBuf,
+    options: &Options,
 ) -> Result<(), BitCaskError> {
     // step 2: iterate through the mem_index, and write the entries to the new log file
     std::fs::create_dir_all(&new_log_file_path)?;
     let mut new_log_file = DiskLogFile::new(&new_log_file_path, 0)?;
-    let mut mem_index = MemIndex::new();
-    let disk_logs = DiskLog::immutable_initialization(immutable_files, &mut mem_index)?;
-    let iter = mem_index.into_iter();
-    for (key, mem_index_entry) in iter {
-        let value = disk_logs.get(&mem_index_entry)?;
-        let disk_log_entry = DiskLogEntry::new_entry(key, value);
-        new_log_file.append_new_entry(disk_log_entry)?;
+    let mut mem_index = new_index(options.index);
+    let mut disk_logs = DiskLog::immutable_initialization(
+        immutable_files,
+        mem_index.as_mut(),
+        &mut Tombstones::new(),
+    )?;
+    disk_logs.configure(options);
+    let mut value_format = ValueFormat::new(options);
+    let (mut keys, mut bytes) = (0u64, 0u64);
+    for (key, mem_index_entry) in mem_index.iter() {
+        keys += 1;
+        if mem_index_entry.flags & FLAG_BLOB != 0 {
+            let pointer = disk_logs.get_stored(&mem_index_entry)?;
+            let entry = DiskLogEntryRef::new_entry(&key, &pointer).with_flags(mem_index_entry.flags);
+            bytes += entry.total_byte_size();
+            new_log_file.append_new_entry(entry)?;
+            continue;
+        }
+        let value = disk_logs.get(&key, &mem_index_entry)?;
+        let (flags, value) = value_format.encode(&key, &value)?;
+        let entry = DiskLogEntryRef::new_entry(&key, &value).with_flags(flags);
+        bytes += entry.total_byte_size();
+        new_log_file.append_new_entry(entry)?;
     }
+    Span::current().record("keys", keys).record("bytes", bytes);
     Ok(())
 }
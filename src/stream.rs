@@ -0,0 +1,254 @@
+use crate::error::BitCaskError;
+use crate::log_entry::checksum_digest;
+use crc::Digest;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Copy exactly `len` bytes from `reader` to `writer`, and return their checksum.
+pub(crate) fn copy_with_checksum<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    len: u64,
+) -> Result<u32, BitCaskError> {
+    let mut digest = checksum_digest();
+    let mut reader = reader.take(len);
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE.min(len as usize)];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        digest.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    if copied != len {
+        return Err(unexpected_end(copied, len));
+    }
+    Ok(digest.finalize())
+}
+
+pub(crate) fn unexpected_end(read: u64, len: u64) -> BitCaskError {
+    BitCaskError::IoError(std::io::Error::new(
+        ErrorKind::UnexpectedEof,
+        format!("the reader ended after {} of {} bytes", read, len),
+    ))
+}
+
+/// A value read from the reader of [`crate::bitcask::BitCask::put_from_reader`] before the storage is locked, so that
+/// a slow reader does not hold up the other operations.
+pub(crate) enum StagedValue {
+    /// A value that has to be compressed or encrypted, which happens in memory anyway.
+    Memory(Vec<u8>),
+    /// A value stored as it is, streamed to a staging file.
+    File(StagingFile),
+}
+
+impl StagedValue {
+    /// Read `len` bytes from `reader`, into a staging file in `dir` if the value is `stored_as_is`.
+    pub(crate) fn read<R: Read>(dir: &Path, stored_as_is: bool, reader: R, len: u64) -> Result<Self, BitCaskError> {
+        if len == 0 || !stored_as_is {
+            let mut value = Vec::new();
+            reader.take(len).read_to_end(&mut value)?;
+            if value.len() as u64 != len {
+                return Err(unexpected_end(value.len() as u64, len));
+            }
+            return Ok(StagedValue::Memory(value));
+        }
+        Ok(StagedValue::File(StagingFile::new(dir, reader, len)?))
+    }
+}
+
+/// A file holding a single value, which is removed when dropped unless it was persisted under another name.
+pub(crate) struct StagingFile {
+    path: PathBuf,
+    pub(crate) file: File,
+    pub(crate) len: u64,
+    pub(crate) check_sum: u32,
+    persisted: bool,
+}
+
+impl StagingFile {
+    pub(crate) const EXT: &'static str = "staging";
+
+    fn new<R: Read>(dir: &Path, reader: R, len: u64) -> Result<Self, BitCaskError> {
+        // staging files of the same process are told apart by a counter
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let name = format!("{}-{}", std::process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let path = dir.join(name).with_extension(Self::EXT);
+        let file = std::fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        let mut staging_file = StagingFile {
+            path,
+            file,
+            len,
+            check_sum: 0,
+            persisted: false,
+        };
+        let mut writer = BufWriter::new(&staging_file.file);
+        let check_sum = copy_with_checksum(reader, &mut writer, len)?;
+        writer.flush()?;
+        drop(writer);
+        staging_file.check_sum = check_sum;
+        Ok(staging_file)
+    }
+
+    /// A reader of the value, from its start.
+    pub(crate) fn reader(&self) -> Result<ValueReader, BitCaskError> {
+        Ok(ValueReader::region(self.file.try_clone()?, 0, self.len, None))
+    }
+
+    /// Move the file to `path`, where it is kept.
+    pub(crate) fn persist(&mut self, path: &Path) -> Result<(), BitCaskError> {
+        std::fs::rename(&self.path, path)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for StagingFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A reader of a single value, see [`crate::bitcask::BitCask::get_reader`].
+pub(crate) enum ValueReader {
+    /// A value stored as is, read from its region of a log or blob file with positional reads.
+    Region {
+        file: File,
+        offset: u64,
+        len: u64,
+        position: u64,
+        /// The running checksum and the expected one, as long as the value is read in order from the start.
+        verify: Option<(Digest<'static, u32>, u32)>,
+    },
+    /// A value that had to be decoded in memory.
+    Decoded(Cursor<Vec<u8>>),
+}
+
+impl ValueReader {
+    pub(crate) fn region(file: File, offset: u64, len: u64, check_sum: Option<u32>) -> Self {
+        ValueReader::Region {
+            file,
+            offset,
+            len,
+            position: 0,
+            verify: check_sum.map(|check_sum| (checksum_digest(), check_sum)),
+        }
+    }
+
+    pub(crate) fn decoded(value: Vec<u8>) -> Self {
+        ValueReader::Decoded(Cursor::new(value))
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (file, offset, len, position, verify) = match self {
+            ValueReader::Decoded(cursor) => return cursor.read(buf),
+            ValueReader::Region {
+                file,
+                offset,
+                len,
+                position,
+                verify,
+            } => (file, offset, len, position, verify),
+        };
+        let wanted = (buf.len() as u64).min(len.saturating_sub(*position)) as usize;
+        if wanted == 0 {
+            return Ok(0);
+        }
+        let n = read_at(file, &mut buf[..wanted], *offset + *position)?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the file ends before the value",
+            ));
+        }
+        *position += n as u64;
+        if let Some((digest, _)) = verify {
+            digest.update(&buf[..n]);
+        }
+        if *position == *len {
+            if let Some((digest, check_sum)) = verify.take() {
+                if digest.finalize() != check_sum {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "invalid checksum of the value",
+                    ));
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for ValueReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (len, position, verify) = match self {
+            ValueReader::Decoded(cursor) => return cursor.seek(pos),
+            ValueReader::Region {
+                len,
+                position,
+                verify,
+                ..
+            } => (len, position, verify),
+        };
+        let new_position = match pos {
+            SeekFrom::Start(new_position) => Some(new_position),
+            SeekFrom::End(delta) => len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )
+        })?;
+        if new_position != *position {
+            // only a value read in order from the start can be verified
+            *verify = None;
+        }
+        *position = new_position;
+        Ok(new_position)
+    }
+}
+
+/// Fill `buf` from `offset` of `file`, without moving the cursor of the file.
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
@@ -0,0 +1,272 @@
+use crate::bitcask::{BitCask, KVStorage, Key, PutOption};
+use crate::error::BitCaskError;
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+
+/// A key type with an order-preserving binary encoding: `a < b` if and only if `a.encode_key() < b.encode_key()`
+/// byte-wise. That is what makes range scans over the key-ordered index meaningful for typed keys.
+///
+/// Integers are encoded big-endian, with the sign bit flipped for signed integers. Strings and byte strings are
+/// escaped (`0x00` becomes `0x00 0xff`) and terminated by `0x00 0x00`, so that a shorter string sorts before a longer
+/// one it is a prefix of, even inside a tuple. Tuples are the concatenation of their elements.
+pub trait OrderedKey: Sized {
+    fn encode_key_to(&self, buf: &mut Vec<u8>);
+    /// Decode a key from the beginning of `buf` and advance `buf` past it.
+    fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError>;
+
+    fn encode_key(&self) -> Key {
+        let mut buf = Vec::new();
+        self.encode_key_to(&mut buf);
+        buf
+    }
+
+    fn decode_key(mut buf: &[u8]) -> Result<Self, BitCaskError> {
+        let key = Self::decode_key_from(&mut buf)?;
+        if !buf.is_empty() {
+            return Err(BitCaskError::CorruptedData(
+                "trailing bytes after key".to_string(),
+            ));
+        }
+        Ok(key)
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], BitCaskError> {
+    if buf.len() < len {
+        return Err(BitCaskError::CorruptedData("key is too short".to_string()));
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+macro_rules! impl_ordered_key_for_unsigned {
+    ($($t:ty),*) => {$(
+        impl OrderedKey for $t {
+            fn encode_key_to(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_be_bytes());
+            }
+            fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError> {
+                let bytes = take(buf, std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_be_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_ordered_key_for_signed {
+    ($($t:ty => $u:ty),*) => {$(
+        impl OrderedKey for $t {
+            fn encode_key_to(&self, buf: &mut Vec<u8>) {
+                // flipping the sign bit makes negative numbers sort before positive ones
+                ((*self as $u) ^ (1 << (<$u>::BITS - 1))).encode_key_to(buf);
+            }
+            fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError> {
+                Ok((<$u>::decode_key_from(buf)? ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+    )*};
+}
+
+impl_ordered_key_for_unsigned!(u8, u16, u32, u64, u128);
+impl_ordered_key_for_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+impl OrderedKey for bool {
+    fn encode_key_to(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+    fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError> {
+        match take(buf, 1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(BitCaskError::CorruptedData("invalid bool".to_string())),
+        }
+    }
+}
+
+impl OrderedKey for Vec<u8> {
+    fn encode_key_to(&self, buf: &mut Vec<u8>) {
+        for byte in self {
+            buf.push(*byte);
+            if *byte == 0 {
+                buf.push(0xff);
+            }
+        }
+        buf.extend_from_slice(&[0, 0]);
+    }
+    fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError> {
+        let mut bytes = Vec::new();
+        loop {
+            match take(buf, 1)?[0] {
+                0 => match take(buf, 1)?[0] {
+                    0 => return Ok(bytes),
+                    0xff => bytes.push(0),
+                    _ => return Err(BitCaskError::CorruptedData("invalid escape".to_string())),
+                },
+                byte => bytes.push(byte),
+            }
+        }
+    }
+}
+
+impl OrderedKey for String {
+    fn encode_key_to(&self, buf: &mut Vec<u8>) {
+        // the escaping works on bytes, and utf-8 preserves the order of code points
+        self.as_bytes().to_vec().encode_key_to(buf);
+    }
+    fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError> {
+        String::from_utf8(Vec::<u8>::decode_key_from(buf)?)
+            .map_err(|_| BitCaskError::CorruptedData("key is not valid utf-8".to_string()))
+    }
+}
+
+macro_rules! impl_ordered_key_for_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: OrderedKey),+> OrderedKey for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key_to(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key_to(buf);)+
+            }
+            fn decode_key_from(buf: &mut &[u8]) -> Result<Self, BitCaskError> {
+                Ok(($($name::decode_key_from(buf)?,)+))
+            }
+        }
+    };
+}
+
+impl_ordered_key_for_tuple!(A);
+impl_ordered_key_for_tuple!(A, B);
+impl_ordered_key_for_tuple!(A, B, C);
+impl_ordered_key_for_tuple!(A, B, C, D);
+
+/// Turns values into bytes and back.
+pub trait Codec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BitCaskError>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BitCaskError>;
+}
+
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BitCaskError> {
+        Ok(serde_json::to_vec(value).map_err(|e| anyhow!(e))?)
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BitCaskError> {
+        serde_json::from_slice(bytes).map_err(|e| BitCaskError::CorruptedData(e.to_string()))
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BitCaskError> {
+        Ok(bincode::serialize(value).map_err(|e| anyhow!(e))?)
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BitCaskError> {
+        bincode::deserialize(bytes).map_err(|e| BitCaskError::CorruptedData(e.to_string()))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, BitCaskError> {
+        Ok(rmp_serde::to_vec(value).map_err(|e| anyhow!(e))?)
+    }
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BitCaskError> {
+        rmp_serde::from_slice(bytes).map_err(|e| BitCaskError::CorruptedData(e.to_string()))
+    }
+}
+
+/// Makes [`TypedBitCask`] `Send` and `Sync` regardless of the key, value and codec types it never stores.
+type TypeMarker<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+/// A [`KVStorage`] with typed keys and values. Keys are encoded with [`OrderedKey`], values with the codec `C`.
+pub struct TypedBitCask<K, V, C = JsonCodec, S = BitCask> {
+    storage: S,
+    _marker: TypeMarker<K, V, C>,
+}
+
+impl<K, V, C, S: Clone> Clone for TypedBitCask<K, V, C, S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V, C, S> TypedBitCask<K, V, C, S>
+where
+    K: OrderedKey,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+    S: KVStorage,
+{
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The untyped storage underneath.
+    pub fn inner(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>, BitCaskError> {
+        self.storage
+            .try_get(&key.encode_key())?
+            .map(|value| C::decode(&value))
+            .transpose()
+    }
+
+    pub fn put(&mut self, key: &K, value: &V) -> Result<(), BitCaskError> {
+        self.put_with_option(key, value, PutOption::none())
+    }
+
+    pub fn put_with_option(
+        &mut self,
+        key: &K,
+        value: &V,
+        option: Option<PutOption>,
+    ) -> Result<(), BitCaskError> {
+        self.storage
+            .put_with_option(&key.encode_key(), &C::encode(value)?, option)
+    }
+
+    pub fn delete(&mut self, key: &K) -> Result<(), BitCaskError> {
+        self.storage.delete(&key.encode_key())
+    }
+
+    pub fn size(&self) -> usize {
+        self.storage.size()
+    }
+
+    /// Return all the key-value pairs whose key is within `range`, in the order of the keys.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<Vec<(K, V)>, BitCaskError> {
+        let encode_bound = |bound: Bound<&K>| match bound {
+            Bound::Included(key) => Bound::Included(key.encode_key()),
+            Bound::Excluded(key) => Bound::Excluded(key.encode_key()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let range = (
+            encode_bound(range.start_bound()),
+            encode_bound(range.end_bound()),
+        );
+        self.storage
+            .range(range)?
+            .into_iter()
+            .map(|(key, value)| Ok((K::decode_key(&key)?, C::decode(&value)?)))
+            .collect()
+    }
+}
@@ -0,0 +1,95 @@
+use crate::bitcask::{Options, Value};
+use crate::compression::{compress, decompress, Compression};
+#[cfg(feature = "encryption")]
+use crate::encryption::Cipher;
+use crate::error::BitCaskError;
+#[cfg(feature = "encryption")]
+use crate::log_entry::checksum;
+use crate::log_entry::FLAG_ENCRYPTED;
+use crate::memory_index::MemIndexEntry;
+use std::borrow::Cow;
+
+/// Turns values into the bytes stored in records and back: values are compressed first, then encrypted.
+#[derive(Clone)]
+pub(crate) struct ValueFormat {
+    compression: Compression,
+    compression_threshold: usize,
+    #[cfg(feature = "encryption")]
+    cipher: Option<Cipher>,
+}
+
+impl ValueFormat {
+    pub(crate) fn new(options: &Options) -> Self {
+        Self {
+            compression: options.compression,
+            compression_threshold: options.compression_threshold,
+            #[cfg(feature = "encryption")]
+            cipher: options
+                .encryption_key
+                .as_ref()
+                .map(|key| Cipher::new(key, &options.old_encryption_keys)),
+        }
+    }
+
+    /// Whether values are stored as they are, which lets them be streamed to and from disk.
+    pub(crate) fn is_identity(&self) -> bool {
+        #[cfg(feature = "encryption")]
+        if self.cipher.is_some() {
+            return false;
+        }
+        self.compression == Compression::None
+    }
+
+    /// Start a new sequence of nonces, for a log file that is opened for appending.
+    pub(crate) fn reset_nonces(&mut self) {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &mut self.cipher {
+            cipher.reset_nonces();
+        }
+    }
+
+    /// Return the flags of the record and the bytes to store for a non-empty `value` of `key`.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub(crate) fn encode<'a>(&mut self, key: &[u8], value: &'a [u8]) -> Result<(u8, Cow<'a, [u8]>), BitCaskError> {
+        let (flags, value) = compress(value, self.compression, self.compression_threshold)?;
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &mut self.cipher {
+            return Ok((flags | FLAG_ENCRYPTED, Cow::Owned(cipher.encrypt(key, &value))));
+        }
+        Ok((flags, value))
+    }
+
+    /// Turn the bytes stored for `mem_index_entry` of `key` back into the value.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    pub(crate) fn decode(
+        &self,
+        key: &[u8],
+        mem_index_entry: &MemIndexEntry,
+        stored: Vec<u8>,
+    ) -> Result<Value, BitCaskError> {
+        let flags = mem_index_entry.flags;
+        if flags & FLAG_ENCRYPTED == 0 {
+            return decompress(flags, stored);
+        }
+        #[cfg(feature = "encryption")]
+        {
+            let cipher = self
+                .cipher
+                .as_ref()
+                .ok_or(BitCaskError::MissingEncryptionKey)?;
+            match cipher.decrypt(key, &stored) {
+                Some(value) => decompress(flags, value),
+                // an intact record that does not authenticate was encrypted with another key
+                None if checksum(&stored) == mem_index_entry.check_sum => Err(BitCaskError::WrongEncryptionKey),
+                None => Err(BitCaskError::CorruptedData(format!(
+                    "invalid encrypted value at offset {} of file {}",
+                    mem_index_entry.value_offset, mem_index_entry.file_id
+                ))),
+            }
+        }
+        #[cfg(not(feature = "encryption"))]
+        Err(BitCaskError::UnexpectedError(anyhow::anyhow!(
+            "value is encrypted, enable the `encryption` feature to read it"
+        )))
+    }
+}
@@ -0,0 +1,294 @@
+use crate::bitcask::FileId;
+use crate::blob_file::{BlobFile, BlobPointer};
+use crate::error::BitCaskError;
+use crate::log_entry::{DiskLogEntry, DiskLogEntryHeader, FLAG_BLOB};
+use crate::log_file::DiskLogFile;
+use crate::memory_index::{BTreeIndex, Index, MemIndexEntry};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// A problem found by [`verify`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// A log file could not be opened or read.
+    UnreadableFile { path: String, error: String },
+    /// Several log files share the same file id, e.g. `1.bitcask` and `01.bitcask`.
+    DuplicateFileId { file_id: FileId, paths: Vec<String> },
+    /// A log file is missing between two existing ones.
+    MissingFileId { file_id: FileId },
+    /// The checksum of a record does not match its value.
+    ChecksumMismatch { file_id: FileId, offset: u64 },
+    /// The sizes in a record header point past the end of the file and are too large to be a torn append, or the value
+    /// of a blob record is not the size of a blob pointer.
+    InvalidRecordSize {
+        file_id: FileId,
+        offset: u64,
+        key_size: u64,
+        value_size: u64,
+    },
+    /// A record has flags this version does not know about.
+    UnknownFlags { file_id: FileId, offset: u64, flags: u8 },
+    /// The file ends in the middle of a record.
+    TruncatedTail {
+        file_id: FileId,
+        offset: u64,
+        trailing_bytes: u64,
+    },
+    /// The live record of a key points to a blob file that does not exist.
+    MissingBlobFile {
+        file_id: FileId,
+        offset: u64,
+        blob_file_id: FileId,
+    },
+    /// The live record of a key points past the end of its blob file.
+    BlobPastEof {
+        file_id: FileId,
+        offset: u64,
+        blob_file_id: FileId,
+        blob_end: u64,
+        blob_file_size: u64,
+    },
+}
+
+/// What [`verify`] found about a single log file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileReport {
+    pub file_id: FileId,
+    pub path: String,
+    pub size: u64,
+    /// Number of valid records, including tombstones.
+    pub records: u64,
+    pub tombstones: u64,
+    /// Number of bytes from the beginning of the file that hold valid records.
+    pub valid_bytes: u64,
+}
+
+/// The result of [`verify`], serializable as JSON with [`VerifyReport::to_json`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerifyReport {
+    pub data_dir: String,
+    pub ok: bool,
+    pub files: Vec<FileReport>,
+    pub live_keys: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Walk every log file of `data_dir` and check it for corruption, without modifying anything.
+///
+/// Every record is checked for a sane header and a matching checksum. Scanning a file stops at the first bad record,
+/// since the rest of the file cannot be trusted to be aligned to records. The live values stored in blob files must
+/// lie within them, but the blob files are not read. A gap in the file ids right after file 0
+/// is expected, because compaction writes the compacted data to file 0 and keeps the ids of the files written
+/// since, but any other gap is reported as a missing file.
+pub fn verify<P: AsRef<Path>>(data_dir: P) -> Result<VerifyReport, BitCaskError> {
+    let data_dir = data_dir.as_ref();
+    let mut problems = Vec::new();
+
+    let mut files_by_id: BTreeMap<FileId, Vec<PathBuf>> = BTreeMap::new();
+    for path in std::fs::read_dir(data_dir)? {
+        let path = path?.path();
+        if !path.is_file() || path.extension() != Some(OsStr::new(DiskLogFile::EXT)) {
+            continue;
+        }
+        let file_id = path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .and_then(|file_stem| file_stem.parse::<FileId>().ok());
+        match file_id {
+            Some(file_id) => files_by_id.entry(file_id).or_default().push(path),
+            None => problems.push(Problem::UnreadableFile {
+                path: path.display().to_string(),
+                error: "file name is not a file id".to_string(),
+            }),
+        }
+    }
+
+    let file_ids: Vec<FileId> = files_by_id.keys().copied().collect();
+    if file_ids.first().is_some_and(|file_id| *file_id != 0) {
+        problems.push(Problem::MissingFileId { file_id: 0 });
+    }
+    for pair in file_ids.windows(2).skip_while(|pair| pair[0] == 0) {
+        problems.extend((pair[0] + 1..pair[1]).map(|file_id| Problem::MissingFileId { file_id }));
+    }
+
+    let mut files = Vec::new();
+    let mut mem_index = BTreeIndex::new();
+    let mut blob_pointers = BTreeMap::new();
+    for (file_id, mut paths) in files_by_id {
+        paths.sort();
+        if paths.len() > 1 {
+            problems.push(Problem::DuplicateFileId {
+                file_id,
+                paths: paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+            });
+        }
+        for path in paths {
+            match verify_file(file_id, &path, &mut mem_index, &mut blob_pointers, &mut problems) {
+                Ok(file_report) => files.push(file_report),
+                Err(e) => problems.push(Problem::UnreadableFile {
+                    path: path.display().to_string(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    let mut blob_file_sizes = BTreeMap::new();
+    for path in std::fs::read_dir(data_dir)? {
+        let path = path?.path();
+        if !path.is_file() || path.extension() != Some(OsStr::new(BlobFile::EXT)) {
+            continue;
+        }
+        let file_id = path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .and_then(|file_stem| file_stem.parse::<FileId>().ok());
+        if let Some(file_id) = file_id {
+            blob_file_sizes.insert(file_id, path.metadata()?.len());
+        }
+    }
+    for (file_id, offset, pointer) in blob_pointers.into_values() {
+        match blob_file_sizes.get(&pointer.file_id) {
+            None => problems.push(Problem::MissingBlobFile {
+                file_id,
+                offset,
+                blob_file_id: pointer.file_id,
+            }),
+            Some(&blob_file_size) => {
+                let blob_end = pointer.offset.saturating_add(pointer.size);
+                if blob_end > blob_file_size {
+                    problems.push(Problem::BlobPastEof {
+                        file_id,
+                        offset,
+                        blob_file_id: pointer.file_id,
+                        blob_end,
+                        blob_file_size,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(VerifyReport {
+        data_dir: data_dir.display().to_string(),
+        ok: problems.is_empty(),
+        files,
+        live_keys: mem_index.size(),
+        problems,
+    })
+}
+
+fn verify_file(
+    file_id: FileId,
+    path: &Path,
+    mem_index: &mut dyn Index,
+    blob_pointers: &mut BTreeMap<Vec<u8>, (FileId, u64, BlobPointer)>,
+    problems: &mut Vec<Problem>,
+) -> Result<FileReport, BitCaskError> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut report = FileReport {
+        file_id,
+        path: path.display().to_string(),
+        size,
+        records: 0,
+        tombstones: 0,
+        valid_bytes: 0,
+    };
+    let mut header_buf = [0u8; DiskLogEntryHeader::BYTE_SIZE];
+    while report.valid_bytes < size {
+        let offset = report.valid_bytes;
+        let remaining = size - offset;
+        let truncated_tail = Problem::TruncatedTail {
+            file_id,
+            offset,
+            trailing_bytes: remaining,
+        };
+        if remaining < DiskLogEntry::header_byte_size() {
+            problems.push(truncated_tail);
+            break;
+        }
+        reader.read_exact(&mut header_buf)?;
+        let header = DiskLogEntryHeader::from_bytes(&header_buf);
+        if !header.has_known_flags() {
+            problems.push(Problem::UnknownFlags {
+                file_id,
+                offset,
+                flags: header.flags,
+            });
+            break;
+        }
+        if header.total_byte_size() > remaining {
+            // a torn append leaves a plausible header behind, sizes beyond a whole log file are garbage
+            if header.total_byte_size() > DiskLogFile::MAX_FILE_SIZE {
+                problems.push(Problem::InvalidRecordSize {
+                    file_id,
+                    offset,
+                    key_size: header.key_size,
+                    value_size: header.value_size,
+                });
+            } else {
+                problems.push(truncated_tail);
+            }
+            break;
+        }
+        let mut key = vec![0u8; header.key_size as usize];
+        reader.read_exact(&mut key)?;
+        let mut value = vec![0u8; header.value_size as usize];
+        reader.read_exact(&mut value)?;
+        if !header.is_valid_for(&value) {
+            problems.push(Problem::ChecksumMismatch { file_id, offset });
+            break;
+        }
+        if header.flags & FLAG_BLOB != 0 {
+            match BlobPointer::decode(&value) {
+                Ok(pointer) => blob_pointers.insert(key.clone(), (file_id, offset, pointer)),
+                Err(_) => {
+                    problems.push(Problem::InvalidRecordSize {
+                        file_id,
+                        offset,
+                        key_size: header.key_size,
+                        value_size: header.value_size,
+                    });
+                    break;
+                }
+            };
+        } else {
+            blob_pointers.remove(&key);
+        }
+        report.records += 1;
+        if value.is_empty() {
+            report.tombstones += 1;
+            mem_index.delete(&key);
+        } else {
+            let value_offset = offset + DiskLogEntry::header_byte_size() + header.key_size;
+            mem_index.put(
+                key,
+                MemIndexEntry {
+                    file_id,
+                    value_offset,
+                    value_size: header.value_size,
+                    check_sum: header.check_sum,
+                    flags: header.flags,
+                },
+            );
+        }
+        report.valid_bytes += header.total_byte_size();
+    }
+    Ok(report)
+}
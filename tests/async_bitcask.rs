@@ -0,0 +1,46 @@
+#![cfg(feature = "tokio")]
+
+use bitcask_engine_rs::async_bitcask::AsyncBitCask;
+use bitcask_engine_rs::bitcask::PutOption;
+use rand::Rng;
+
+#[tokio::test]
+async fn async_put_get_delete() {
+    let bitcask = AsyncBitCask::new(generate_random_data_dir()).await.unwrap();
+    bitcask.put(vec![1, 2, 3], vec![4, 5, 6]).await.unwrap();
+    assert_eq!(bitcask.get(vec![1, 2, 3]).await.unwrap(), Some(vec![4, 5, 6]));
+    bitcask
+        .put_with_option(vec![1, 2, 3], vec![7], PutOption::nx())
+        .await
+        .unwrap_err();
+    bitcask.delete(vec![1, 2, 3]).await.unwrap();
+    assert_eq!(bitcask.get(vec![1, 2, 3]).await.unwrap(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_concurrent_puts_and_compaction() {
+    let bitcask = AsyncBitCask::new(generate_random_data_dir()).await.unwrap();
+    let handles: Vec<_> = (0..16u8)
+        .map(|i| {
+            let bitcask = bitcask.clone();
+            tokio::spawn(async move { bitcask.put(vec![i], vec![i, i]).await })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap().unwrap();
+    }
+    bitcask.compact(generate_random_data_dir()).await.unwrap();
+    for i in 0..16u8 {
+        assert_eq!(bitcask.get(vec![i]).await.unwrap(), Some(vec![i, i]));
+    }
+}
+
+fn generate_random_data_dir() -> String {
+    let rng = rand::thread_rng();
+    let rand_string: String = rng
+        .sample_iter(rand::distributions::Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    format!("./data/{}", rand_string)
+}
@@ -0,0 +1,125 @@
+use bitcask_engine_rs::bitcask::BitCask;
+use bitcask_engine_rs::memcached::MemcachedServer;
+use rand::Rng;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[test]
+fn memcached_storage_commands() {
+    let mut client = start_server();
+    assert_eq!(client.request("set foo 5 0 3\r\nbar\r\n"), "STORED\r\n");
+    assert_eq!(
+        client.request("get foo\r\n"),
+        "VALUE foo 5 3\r\nbar\r\nEND\r\n"
+    );
+    assert_eq!(client.request("add foo 0 0 1\r\nx\r\n"), "NOT_STORED\r\n");
+    assert_eq!(
+        client.request("replace missing 0 0 1\r\nx\r\n"),
+        "NOT_STORED\r\n"
+    );
+    assert_eq!(client.request("add new 0 0 1\r\nx\r\n"), "STORED\r\n");
+    assert_eq!(client.request("replace new 0 0 1\r\ny\r\n"), "STORED\r\n");
+    assert_eq!(
+        client.request("get new missing\r\n"),
+        "VALUE new 0 1\r\ny\r\nEND\r\n"
+    );
+    assert_eq!(client.request("delete new\r\n"), "DELETED\r\n");
+    assert_eq!(client.request("delete new\r\n"), "NOT_FOUND\r\n");
+    // an item with a negative expiration time is expired immediately
+    assert_eq!(client.request("set gone 0 -1 1\r\nx\r\n"), "STORED\r\n");
+    assert_eq!(client.request("get gone\r\n"), "END\r\n");
+}
+
+#[test]
+fn memcached_cas_and_counters() {
+    let mut client = start_server();
+    assert_eq!(client.request("set counter 0 0 2\r\n10\r\n"), "STORED\r\n");
+    assert_eq!(client.request("incr counter 5\r\n"), "15\r\n");
+    assert_eq!(client.request("decr counter 20\r\n"), "0\r\n");
+    assert_eq!(client.request("incr missing 1\r\n"), "NOT_FOUND\r\n");
+
+    let response = client.request("gets counter\r\n");
+    let cas: u64 = response
+        .lines()
+        .next()
+        .unwrap()
+        .split(' ')
+        .nth(4)
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        client.request(&format!("cas counter 0 0 1 {}\r\n7\r\n", cas + 1)),
+        "EXISTS\r\n"
+    );
+    assert_eq!(
+        client.request(&format!("cas counter 0 0 1 {}\r\n7\r\n", cas)),
+        "STORED\r\n"
+    );
+    assert_eq!(
+        client.request(&format!("cas counter 0 0 1 {}\r\n8\r\n", cas)),
+        "EXISTS\r\n"
+    );
+    assert_eq!(
+        client.request("cas missing 0 0 1 1\r\n8\r\n"),
+        "NOT_FOUND\r\n"
+    );
+    assert_eq!(
+        client.request("get counter\r\n"),
+        "VALUE counter 0 1\r\n7\r\nEND\r\n"
+    );
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    /// Send a request and read the response up to its terminating line.
+    fn request(&mut self, request: &str) -> String {
+        self.writer.write_all(request.as_bytes()).unwrap();
+        let retrieval = request.starts_with("get");
+        let mut response = String::new();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            response.push_str(&line);
+            if !retrieval || line == "END\r\n" {
+                return response;
+            }
+            if let Some(len) = line
+                .strip_prefix("VALUE ")
+                .and_then(|header| header.split(' ').nth(2))
+            {
+                let len: usize = len.trim().parse().unwrap();
+                let mut data = vec![0u8; len + 2];
+                self.reader.read_exact(&mut data).unwrap();
+                response.push_str(&String::from_utf8(data).unwrap());
+            }
+        }
+    }
+}
+
+fn start_server() -> Client {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let bitcask = BitCask::new(data_dir).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || MemcachedServer::new(bitcask).serve(listener));
+    let stream = TcpStream::connect(addr).unwrap();
+    Client {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+    }
+}
+
+fn generate_random_name() -> String {
+    let rng = rand::thread_rng();
+    let rand_string: String = rng
+        .sample_iter(rand::distributions::Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    rand_string
+}
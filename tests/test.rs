@@ -1,46 +1,985 @@
+// the tests written against the `&Vec<u8>` API still pass vectors, which the slice API accepts as they are
+#![allow(clippy::useless_vec, clippy::unnecessary_to_owned)]
+
 use rand::Rng;
-use bitcask_engine_rs::bitcask::{BitCask, KVStorage, PutOption};
+use bitcask_engine_rs::bitcask::{BitCask, IndexType, KVStorage, Options, PutOption};
+use bitcask_engine_rs::error::BitCaskError;
+use bitcask_engine_rs::export::{Encoding, ExportFormat};
+use bitcask_engine_rs::inspect::LogFileReader;
+use std::ops::Bound;
 
 #[test]
 fn it_works() {
     let mut bitcask = generate_random_bitcask_instance();
-    bitcask.put(&vec![1, 2, 3], &vec![4, 5, 6]).unwrap();
-    let res = bitcask.get(&vec![1, 2, 3]);
+    bitcask.put(&[1, 2, 3], &[4, 5, 6]).unwrap();
+    let res = bitcask.get(&[1, 2, 3]);
     assert_eq!(res, Some(vec![4, 5, 6]));
 }
 
+#[test]
+fn borrowed_slices() {
+    let mut bitcask = generate_random_bitcask_instance();
+    let buf = [0u8, 1, 2, 3, 4, 5];
+    bitcask.put(&buf[..2], &buf[2..]).unwrap();
+    assert_eq!(bitcask.get(&buf[..2]), Some(vec![2, 3, 4, 5]));
+    #[cfg(feature = "bytes")]
+    assert_eq!(
+        bitcask.get_bytes(&buf[..2]),
+        Some(bytes::Bytes::from_static(&[2, 3, 4, 5]))
+    );
+    bitcask.delete(&buf[..2]).unwrap();
+    assert_eq!(bitcask.get(&buf[..2]), None);
+}
+
+#[test]
+fn try_get_surfaces_corruption() {
+    use std::io::{Seek, SeekFrom, Write};
+    let data_dir = format!("./data/{}", generate_random_name());
+    let mut bitcask =
+        BitCask::with_options(data_dir.clone(), Options::default().verify_checksums(true)).unwrap();
+    bitcask.put(&[1, 2, 3], &[4, 5, 6]).unwrap();
+    let unverified = BitCask::new(data_dir.clone()).unwrap();
+    assert_eq!(bitcask.try_get(&[1, 2, 3]).unwrap(), Some(vec![4, 5, 6]));
+    assert_eq!(bitcask.try_get(&[7]).unwrap(), None);
+    // rot the first byte of the value after the data directory has been opened
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("{}/0.bitcask", data_dir))
+        .unwrap();
+    file.seek(SeekFrom::Start(23)).unwrap();
+    file.write_all(&[0]).unwrap();
+    assert!(matches!(
+        bitcask.try_get(&[1, 2, 3]),
+        Err(BitCaskError::CorruptedData(_))
+    ));
+    assert_eq!(bitcask.get(&[1, 2, 3]), None);
+    // without verification the rotten value is returned as is
+    assert_eq!(unverified.try_get(&[1, 2, 3]).unwrap(), Some(vec![0, 5, 6]));
+}
+
+#[test]
+fn verified_reads_across_restart_and_compaction() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().verify_checksums(true);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    bitcask.put(&[1], &[1, 1]).unwrap();
+    bitcask.put(&[2], &[2, 2]).unwrap();
+    drop(bitcask);
+    let mut bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.try_get(&[1]).unwrap(), Some(vec![1, 1]));
+    bitcask.put(&[1], &[3]).unwrap();
+    bitcask
+        .compact_to_new_dir(format!("./data/{}", generate_random_name()))
+        .unwrap();
+    assert_eq!(bitcask.try_get(&[1]).unwrap(), Some(vec![3]));
+    assert_eq!(bitcask.try_get(&[2]).unwrap(), Some(vec![2, 2]));
+}
+
+#[test]
+fn multi_get_and_multi_put() {
+    let mut bitcask = generate_random_bitcask_instance();
+    bitcask
+        .multi_put(&[(vec![3], vec![3]), (vec![1], vec![1]), (vec![2], vec![2]), (vec![1], vec![4])])
+        .unwrap();
+    bitcask.delete(&[2]).unwrap();
+    assert_eq!(
+        bitcask.multi_get(&[vec![1], vec![2], vec![3], vec![5]]),
+        vec![Some(vec![4]), None, Some(vec![3]), None]
+    );
+    assert_eq!(bitcask.try_multi_get::<Vec<u8>>(&[]).unwrap(), vec![]);
+}
+
+#[test]
+fn group_commit_with_concurrent_writers() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().sync(true).group_commit(true);
+    let bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    let threads: Vec<_> = (0..8u8)
+        .map(|t| {
+            let mut bitcask = bitcask.clone();
+            std::thread::spawn(move || {
+                for i in 0..50u8 {
+                    bitcask.put(&[t, i], &[i]).unwrap();
+                    // only one thread can win each shared key
+                    let _ = bitcask.put_with_option(&[255, i], &[t], PutOption::nx());
+                }
+                bitcask.delete(&[t, 0]).unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(bitcask.stats().unwrap().live_keys, 8 * 49 + 50);
+    drop(bitcask);
+
+    let mut bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.get(&[3, 7]), Some(vec![7]));
+    assert_eq!(bitcask.get(&[3, 0]), None);
+    assert!(matches!(
+        bitcask.put_with_option(&[255, 1], &[0], PutOption::nx()),
+        Err(BitCaskError::KeyExists)
+    ));
+    assert!(matches!(
+        bitcask.put_with_option(&[254], &[0], PutOption::xx()),
+        Err(BitCaskError::KeyNotFound)
+    ));
+}
+
+#[test]
+fn hash_index() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().index(IndexType::Hash);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    for i in (0..20u8).rev() {
+        bitcask.put(&[i % 2, i], &[i]).unwrap();
+    }
+    bitcask.delete(&[1, 3]).unwrap();
+    let odd: Vec<u8> = bitcask
+        .scan_prefix(&[1])
+        .unwrap()
+        .into_iter()
+        .map(|(_, value)| value[0])
+        .collect();
+    assert_eq!(odd, vec![1, 5, 7, 9, 11, 13, 15, 17, 19]);
+    let range = bitcask
+        .range((Bound::Included(vec![0, 4]), Bound::Excluded(vec![0, 10])))
+        .unwrap();
+    assert_eq!(range.len(), 3);
+    assert_eq!(range[0], (vec![0, 4], vec![4]));
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(compacted_dir.clone()).unwrap();
+    drop(bitcask);
+    let bitcask = BitCask::with_options(compacted_dir, options).unwrap();
+    assert_eq!(bitcask.get(&[0, 18]), Some(vec![18]));
+    assert_eq!(bitcask.get(&[1, 3]), None);
+    assert_eq!(bitcask.size(), 19);
+}
+
+#[test]
+fn compact_index() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().index(IndexType::Compact);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    let key = |i: u32| format!("user:{:08}", i).into_bytes();
+    // enough keys for the changes to be merged into the packed keys several times
+    let pairs: Vec<_> = (0..20_000).map(|i| (key(i), i.to_be_bytes().to_vec())).collect();
+    bitcask.multi_put(&pairs).unwrap();
+    for i in (0..20_000).step_by(3) {
+        bitcask.delete(&key(i)).unwrap();
+    }
+    bitcask.put(&key(3), b"three").unwrap();
+    bitcask.put(b"user:", b"first").unwrap();
+    assert_eq!(bitcask.get(&key(3)), Some(b"three".to_vec()));
+    assert_eq!(bitcask.get(&key(6)), None);
+    assert_eq!(bitcask.get(&key(7)), Some(7u32.to_be_bytes().to_vec()));
+    let live = 20_000 - 6667 + 2;
+    assert_eq!(bitcask.size(), live);
+    let range = bitcask
+        .range((Bound::Excluded(key(2)), Bound::Included(key(8))))
+        .unwrap();
+    let keys: Vec<_> = range.into_iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, vec![key(3), key(4), key(5), key(7), key(8)]);
+    assert_eq!(bitcask.scan_prefix(b"user:0001999").unwrap().len(), 7);
+    let compact_bytes_per_key = bitcask.stats().unwrap().index_bytes_per_key();
+    drop(bitcask);
+
+    let bitcask = BitCask::with_options(data_dir.clone(), options).unwrap();
+    assert_eq!(bitcask.size(), live);
+    assert_eq!(bitcask.get(&key(19_999)), Some(19_999u32.to_be_bytes().to_vec()));
+    drop(bitcask);
+    let bitcask = BitCask::new(data_dir).unwrap();
+    assert!(compact_bytes_per_key < bitcask.stats().unwrap().index_bytes_per_key() / 2.0);
+}
+
+#[test]
+fn inverted_range_is_empty() {
+    for index in [IndexType::BTree, IndexType::Hash, IndexType::Compact] {
+        let data_dir = format!("./data/{}", generate_random_name());
+        let mut bitcask = BitCask::with_options(data_dir, Options::default().index(index)).unwrap();
+        for i in 0..10u8 {
+            bitcask.put(&[i], &[i]).unwrap();
+        }
+        for range in [
+            (Bound::Included(vec![8]), Bound::Included(vec![2])),
+            (Bound::Excluded(vec![8]), Bound::Excluded(vec![2])),
+            (Bound::Excluded(vec![5]), Bound::Excluded(vec![5])),
+            (Bound::Included(vec![5]), Bound::Excluded(vec![5])),
+            (Bound::Excluded(vec![5]), Bound::Included(vec![5])),
+        ] {
+            assert_eq!(bitcask.range(range).unwrap(), vec![], "{:?}", index);
+        }
+        let single = bitcask
+            .range((Bound::Included(vec![5]), Bound::Included(vec![5])))
+            .unwrap();
+        assert_eq!(single, vec![(vec![5], vec![5])]);
+    }
+}
+
+#[test]
+fn deleted_keys_leave_the_index() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&[1], &[1]).unwrap();
+    bitcask.put(&[2], &[2]).unwrap();
+    bitcask.put(&[3], &[3]).unwrap();
+    bitcask.delete(&[1]).unwrap();
+    bitcask.delete(&[2]).unwrap();
+    bitcask.put(&[2], &[22]).unwrap();
+    bitcask.put(&[3], &[]).unwrap();
+    assert_eq!((bitcask.size(), bitcask.tombstone_count()), (1, 2));
+    assert!(matches!(
+        bitcask.put_with_option(&[1], &[1], PutOption::xx()),
+        Err(BitCaskError::KeyNotFound)
+    ));
+    drop(bitcask);
+
+    let bitcask = BitCask::new(data_dir).unwrap();
+    assert_eq!((bitcask.size(), bitcask.tombstone_count()), (1, 2));
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(compacted_dir).unwrap();
+    assert_eq!((bitcask.size(), bitcask.tombstone_count()), (1, 0));
+    assert_eq!(bitcask.get(&[2]), Some(vec![22]));
+}
+
 #[test]
 fn compaction() {
     let mut bitcask = generate_random_bitcask_instance();
-    bitcask.put(&vec![1, 2, 3], &vec![4, 5, 6]).unwrap();
-    bitcask.put(&vec![1, 2], &vec![3, 4]).unwrap();
-    bitcask.put(&vec![1, 2, 3], &vec![5, 6, 7]).unwrap();
+    bitcask.put(&[1, 2, 3], &[4, 5, 6]).unwrap();
+    bitcask.put(&[1, 2], &[3, 4]).unwrap();
+    bitcask.put(&[1, 2, 3], &[5, 6, 7]).unwrap();
     let new_dir = format!("./data/{}", generate_random_name());
     bitcask.compact_to_new_dir(new_dir.clone()).unwrap();
     // the old bitcask handle automatically switches to the new directory
-    assert_eq!(bitcask.get(&vec![1, 2, 3]), Some(vec![5, 6, 7]));
-    assert_eq!(bitcask.get(&vec![1, 2]), Some(vec![3, 4]));
+    assert_eq!(bitcask.get(&[1, 2, 3]), Some(vec![5, 6, 7]));
+    assert_eq!(bitcask.get(&[1, 2]), Some(vec![3, 4]));
     // the new bitcask handle is also able to read the data
     let bitcask_new = BitCask::new(new_dir).unwrap();
-    assert_eq!(bitcask_new.get(&vec![1, 2, 3]), Some(vec![5, 6, 7]));
-    assert_eq!(bitcask_new.get(&vec![1, 2]), Some(vec![3, 4]));
+    assert_eq!(bitcask_new.get(&[1, 2, 3]), Some(vec![5, 6, 7]));
+    assert_eq!(bitcask_new.get(&[1, 2]), Some(vec![3, 4]));
 }
 
 #[test]
 fn test_put_nx() {
     let mut bitcask = generate_random_bitcask_instance();
-    bitcask.put_with_option(&vec![1, 2, 3], &vec![4, 5, 6], PutOption::nx()).unwrap();
-    let res = bitcask.get(&vec![1, 2, 3]);
+    bitcask.put_with_option(&[1, 2, 3], &[4, 5, 6], PutOption::nx()).unwrap();
+    let res = bitcask.get(&[1, 2, 3]);
     assert_eq!(res, Some(vec![4, 5, 6]));
-    bitcask.put_with_option(&vec![1, 2, 3], &vec![4, 5, 6], PutOption::nx()).unwrap_err();
+    bitcask.put_with_option(&[1, 2, 3], &[4, 5, 6], PutOption::nx()).unwrap_err();
 }
 
 #[test]
 fn test_put_xx() {
     let mut bitcask = generate_random_bitcask_instance();
-    bitcask.put_with_option(&vec![1, 2, 3], &vec![4, 5, 6], PutOption::xx()).unwrap_err();
-    bitcask.put_with_option(&vec![1, 2, 3], &vec![4, 5, 6], PutOption::nx()).unwrap();
-    bitcask.put_with_option(&vec![1, 2, 3], &vec![4, 5, 6], PutOption::xx()).unwrap();
+    bitcask.put_with_option(&[1, 2, 3], &[4, 5, 6], PutOption::xx()).unwrap_err();
+    bitcask.put_with_option(&[1, 2, 3], &[4, 5, 6], PutOption::nx()).unwrap();
+    bitcask.put_with_option(&[1, 2, 3], &[4, 5, 6], PutOption::xx()).unwrap();
+}
+
+#[test]
+fn scan_prefix_and_stats() {
+    let mut bitcask = generate_random_bitcask_instance();
+    bitcask.put(&[1, 2], &[1]).unwrap();
+    bitcask.put(&[1, 3], &[2]).unwrap();
+    bitcask.put(&[2, 1], &[3]).unwrap();
+    bitcask.put(&[1, 2], &[4]).unwrap();
+    let res = bitcask.scan_prefix(&[1]).unwrap();
+    assert_eq!(res, vec![(vec![1, 2], vec![4]), (vec![1, 3], vec![2])]);
+    let stats = bitcask.stats().unwrap();
+    assert_eq!(stats.file_count, 1);
+    assert_eq!(stats.live_keys, 3);
+    // every record is 20 bytes of header, 2 bytes of key and 1 byte of value
+    assert_eq!(stats.total_bytes, 4 * 23);
+    assert_eq!(stats.dead_bytes, 23);
+}
+
+#[test]
+fn dump_log_file() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&vec![1, 2, 3], &vec![4, 5, 6]).unwrap();
+    bitcask.delete(&vec![1, 2, 3]).unwrap();
+    let records = LogFileReader::open(format!("{}/0.bitcask", data_dir))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!((records[0].offset, records[0].tombstone), (0, false));
+    assert_eq!(records[0].value, Some(vec![4, 5, 6]));
+    assert_eq!((records[1].offset, records[1].tombstone), (26, true));
+}
+
+#[cfg(all(feature = "lz4", feature = "zstd"))]
+#[test]
+fn compression() {
+    use bitcask_engine_rs::compression::Compression;
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().compression(Compression::Lz4);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options).unwrap();
+    let json = br#"{"name": "bitcask", "tags": ["kv", "log"]}"#.repeat(50);
+    bitcask.put(&[1], &json).unwrap();
+    bitcask.put(&[2], b"short").unwrap();
+    assert!(bitcask.stats().unwrap().total_bytes < json.len() as u64 / 2);
+    let records = LogFileReader::open(format!("{}/0.bitcask", data_dir))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!((records[0].flags, records[1].flags), (0b01, 0));
+    drop(bitcask);
+
+    // reopen with zstd: old records stay readable, compaction recompresses them
+    let options = Options::default().compression(Compression::Zstd(0)).verify_checksums(true);
+    let bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(json.clone()));
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(compacted_dir.clone()).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(json));
+    assert_eq!(bitcask.get(&[2]), Some(b"short".to_vec()));
+    let records = LogFileReader::open(format!("{}/0.bitcask", compacted_dir))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!((records[0].flags, records[1].flags), (0b10, 0));
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encryption_and_key_rotation() {
+    use bitcask_engine_rs::encryption::EncryptionKey;
+    let data_dir = format!("./data/{}", generate_random_name());
+    let (old_key, new_key) = (EncryptionKey::generate(), EncryptionKey::generate());
+    let options = Options::default().encryption_key(old_key.clone());
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options).unwrap();
+    bitcask.put(b"alice", b"alice@example.com").unwrap();
+    bitcask.put(b"bob", b"bob@example.com").unwrap();
+    bitcask.delete(b"bob").unwrap();
+    drop(bitcask);
+    let file = std::fs::read(format!("{}/0.bitcask", data_dir)).unwrap();
+    assert!(!file.windows(7).any(|window| window == b"example"));
+
+    assert!(matches!(
+        BitCask::with_options(data_dir.clone(), Options::default().encryption_key(new_key.clone())),
+        Err(BitCaskError::WrongEncryptionKey)
+    ));
+    assert!(matches!(
+        BitCask::new(data_dir.clone()),
+        Err(BitCaskError::MissingEncryptionKey)
+    ));
+
+    // compaction rewrites the values with the new key
+    let options = Options::default()
+        .encryption_key(new_key.clone())
+        .old_encryption_keys(vec![old_key]);
+    let bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.get(b"alice"), Some(b"alice@example.com".to_vec()));
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(compacted_dir.clone()).unwrap();
+    drop(bitcask);
+    let options = Options::default().encryption_key(new_key).verify_checksums(true);
+    let bitcask = BitCask::with_options(compacted_dir, options).unwrap();
+    assert_eq!(bitcask.get(b"alice"), Some(b"alice@example.com".to_vec()));
+    assert_eq!(bitcask.get(b"bob"), None);
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_values_are_bound_to_their_keys() {
+    use bitcask_engine_rs::encryption::EncryptionKey;
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().encryption_key(EncryptionKey::generate());
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    bitcask.put(b"alice", b"alice@example.com").unwrap();
+    bitcask.put(b"carol", b"carol@example.com").unwrap();
+    drop(bitcask);
+    // swap the checksums and the values of the two records, which keep their keys: every record is 20 bytes of
+    // header, 5 bytes of key and 57 bytes of nonce, ciphertext and tag
+    let path = format!("{}/0.bitcask", data_dir);
+    let mut file = std::fs::read(&path).unwrap();
+    assert_eq!(file.len(), 2 * 82);
+    let (alice, carol) = file.split_at_mut(82);
+    alice[..4].swap_with_slice(&mut carol[..4]);
+    alice[25..].swap_with_slice(&mut carol[25..]);
+    std::fs::write(&path, file).unwrap();
+    assert!(BitCask::with_options(data_dir, options).is_err());
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_empty_value_deletes() {
+    use bitcask_engine_rs::encryption::EncryptionKey;
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().encryption_key(EncryptionKey::generate());
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    bitcask.put(&[1], &[1; 10]).unwrap();
+    bitcask.put(&[1], &[]).unwrap();
+    assert_eq!(bitcask.try_get(&[1]).unwrap(), None);
+    assert_eq!(bitcask.size(), 0);
+    bitcask.put_with_option(&[1], &[2], PutOption::nx()).unwrap();
+    bitcask.put(&[1], &[]).unwrap();
+    assert!(bitcask.put_with_option(&[1], &[3], PutOption::xx()).is_err());
+    drop(bitcask);
+    let bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.try_get(&[1]).unwrap(), None);
+    assert_eq!(bitcask.size(), 0);
+}
+
+#[test]
+fn blob_files() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().blob_threshold(1024).verify_checksums(true);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    bitcask.put(&[1], &[1; 4096]).unwrap();
+    bitcask.put(&[2], &[2; 16]).unwrap();
+    bitcask.put(&[3], &[3; 4096]).unwrap();
+    let records = LogFileReader::open(format!("{}/0.bitcask", data_dir))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    // the log only holds a pointer to each large value
+    assert_eq!((records[0].flags, records[1].flags), (0b1000, 0));
+    assert_eq!(records[0].value.as_ref().map(Vec::len), Some(28));
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 4096]));
+
+    bitcask.put(&[1], &[4; 4096]).unwrap();
+    bitcask.delete(&[3]).unwrap();
+    assert_eq!(bitcask.stats().unwrap().blob_bytes, 3 * 4096);
+    assert_eq!(bitcask.gc_blobs(0.5).unwrap(), 2 * 4096);
+    assert_eq!(bitcask.stats().unwrap().blob_bytes, 4096);
+    assert_eq!(bitcask.gc_blobs(0.5).unwrap(), 0);
+    assert_eq!(bitcask.get(&[1]), Some(vec![4; 4096]));
+    drop(bitcask);
+
+    let bitcask = BitCask::with_options(data_dir, options.clone()).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![4; 4096]));
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(compacted_dir.clone()).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![4; 4096]));
+    assert_eq!(bitcask.get(&[2]), Some(vec![2; 16]));
+    drop(bitcask);
+    let bitcask = BitCask::with_options(compacted_dir, options).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![4; 4096]));
+    assert_eq!(bitcask.get(&[3]), None);
+}
+
+#[test]
+fn truncated_blob_file() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().blob_threshold(1024);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    bitcask.put(&[1], &[1; 4096]).unwrap();
+    drop(bitcask);
+    let blob_file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("{}/0.blob", data_dir))
+        .unwrap();
+    blob_file.set_len(100).unwrap();
+    let bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert!(matches!(bitcask.try_get(&[1]), Err(BitCaskError::CorruptedData(_))));
+}
+
+#[test]
+fn streaming_reads_and_writes() {
+    use std::io::{Read, Seek, SeekFrom};
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().blob_threshold(1 << 20).verify_checksums(true);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    let value: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+    bitcask.put_from_reader(&[1], value.as_slice(), value.len() as u64).unwrap();
+    let blob: Vec<u8> = (0..(3 << 20) as u32).map(|i| (i / 7) as u8).collect();
+    bitcask.put_from_reader(&[2], blob.as_slice(), blob.len() as u64).unwrap();
+    // a reader that ends early leaves nothing behind
+    let error = bitcask.put_from_reader(&[3], &value[..10], 20).unwrap_err();
+    assert!(matches!(error, BitCaskError::IoError(e) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+    bitcask.put(&[4], &[4]).unwrap();
+    assert_eq!(bitcask.get(&[3]), None);
+
+    let mut streamed = Vec::new();
+    let mut reader = bitcask.get_reader(&[1]).unwrap().unwrap();
+    reader.read_to_end(&mut streamed).unwrap();
+    assert_eq!(streamed, value);
+    reader.seek(SeekFrom::End(-5)).unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &value[value.len() - 5..]);
+    assert!(bitcask.get_reader(&[3]).unwrap().is_none());
+    assert_eq!(bitcask.stats().unwrap().blob_bytes, blob.len() as u64);
+    let staging_files = std::fs::read_dir(&data_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("staging".as_ref()))
+        .count();
+    assert_eq!(staging_files, 0);
+    drop(bitcask);
+
+    let bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(value));
+    assert_eq!(bitcask.get(&[4]), Some(vec![4]));
+    let mut streamed = Vec::new();
+    let mut reader = bitcask.get_reader(&[2]).unwrap().unwrap();
+    reader.read_to_end(&mut streamed).unwrap();
+    assert!(streamed == blob);
+}
+
+#[test]
+fn streaming_write_does_not_block_other_operations() {
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    /// Stops at its first read until it is let through.
+    struct GatedReader {
+        value: &'static [u8],
+        started: Sender<()>,
+        gate: Receiver<()>,
+    }
+
+    impl std::io::Read for GatedReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.started.send(()).is_ok() {
+                self.gate.recv().unwrap();
+            }
+            self.value.read(buf)
+        }
+    }
+
+    for blob_threshold in [None, Some(1024)] {
+        let data_dir = format!("./data/{}", generate_random_name());
+        let mut options = Options::default();
+        if let Some(blob_threshold) = blob_threshold {
+            options = options.blob_threshold(blob_threshold);
+        }
+        let mut bitcask = BitCask::with_options(data_dir, options).unwrap();
+        bitcask.put(&[1], &[1]).unwrap();
+        let (started, started_receiver) = channel();
+        let (gate_sender, gate) = channel();
+        let reader = GatedReader {
+            value: &[2; 4096],
+            started,
+            gate,
+        };
+        let mut writer = bitcask.clone();
+        let streaming = std::thread::spawn(move || writer.put_from_reader(&[2], reader, 4096));
+        started_receiver.recv().unwrap();
+        drop(started_receiver);
+        // the streaming put is stuck on its reader, other operations go on
+        assert_eq!(bitcask.get(&[1]), Some(vec![1]));
+        bitcask.put(&[3], &[3]).unwrap();
+        assert_eq!(bitcask.get(&[2]), None);
+        gate_sender.send(()).unwrap();
+        streaming.join().unwrap().unwrap();
+        assert_eq!(bitcask.get(&[2]), Some(vec![2; 4096]));
+    }
+}
+
+#[test]
+fn read_cache() {
+    let data_dir = format!("./data/{}", generate_random_name());
+    let mut bitcask = BitCask::with_options(data_dir, Options::default().cache_size(64)).unwrap();
+    bitcask.put(&[1], &[1; 16]).unwrap();
+    bitcask.put(&[2], &[2; 16]).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 16]));
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 16]));
+    let stats = bitcask.stats().unwrap();
+    assert_eq!((stats.cache_hits, stats.cache_misses), (1, 1));
+
+    // writes invalidate the cached value
+    bitcask.put(&[1], &[3; 16]).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![3; 16]));
+    bitcask.delete(&[1]).unwrap();
+    assert_eq!(bitcask.get(&[1]), None);
+    // the least recently used value is evicted once the cache is full
+    for key in 2..6 {
+        assert_eq!(bitcask.get(&[2]), Some(vec![2; 16]));
+        bitcask.put(&[key + 1], &[key; 16]).unwrap();
+        bitcask.get(&[key + 1]).unwrap();
+    }
+    let stats = bitcask.stats().unwrap();
+    assert_eq!((stats.cache_hits, stats.cache_misses), (4, 7));
+
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(compacted_dir).unwrap();
+    assert_eq!(bitcask.get(&[2]), Some(vec![2; 16]));
+    assert_eq!(bitcask.stats().unwrap().cache_misses, 8);
+}
+
+#[test]
+fn operation_stats() {
+    let mut bitcask = generate_random_bitcask_instance();
+    bitcask.put(&[1], &[1; 10]).unwrap();
+    bitcask.multi_put(&[([2], [2; 10]), ([3], [3; 10])]).unwrap();
+    bitcask.delete(&[3]).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 10]));
+    assert_eq!(bitcask.multi_get(&[[2], [3]]), vec![Some(vec![2; 10]), None]);
+    bitcask.scan_prefix(&[]).unwrap();
+    let stats = bitcask.stats().unwrap();
+    assert_eq!(
+        (stats.gets.count, stats.puts.count, stats.deletes.count, stats.scans.count),
+        (3, 3, 1, 1)
+    );
+    assert!(stats.puts.max_time <= stats.puts.total_time);
+    // three records of 31 bytes and a tombstone of 21 bytes, and four values read, two of them by the scan
+    assert_eq!((stats.bytes_written, stats.bytes_read), (3 * 31 + 21, 4 * 10));
+    assert_eq!(stats.file_sizes, vec![(0, stats.total_bytes)]);
+
+    // a batch adds its time once, however many pairs it holds
+    let pairs: Vec<_> = (0..2000u32).map(|i| (i.to_be_bytes(), [0u8; 10])).collect();
+    let start = std::time::Instant::now();
+    bitcask.multi_put(&pairs).unwrap();
+    let elapsed = start.elapsed();
+    let puts = bitcask.stats().unwrap().puts;
+    assert_eq!(puts.count, stats.puts.count + 2000);
+    assert!(puts.total_time - stats.puts.total_time <= elapsed);
+    assert!(puts.max_time <= stats.puts.max_time.max(elapsed / 2000));
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus_export() {
+    let mut bitcask = generate_random_bitcask_instance();
+    bitcask.put(&[1], &[1]).unwrap();
+    bitcask.get(&[1]).unwrap();
+    let text = bitcask.stats().unwrap().to_prometheus();
+    assert!(text.contains("# TYPE bitcask_live_keys gauge\nbitcask_live_keys 1\n"));
+    assert!(text.contains("bitcask_operations_total{op=\"get\"} 1\n"));
+    assert!(text.contains("bitcask_file_bytes{file=\"0\"} 22\n"));
+}
+
+#[test]
+fn tracing_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        let mut bitcask = generate_random_bitcask_instance();
+        bitcask.put(&[1, 2, 3], &[4, 5]).unwrap();
+        bitcask.get(&[1, 2, 3]).unwrap();
+        bitcask.delete(&[1, 2, 3]).unwrap();
+        bitcask
+            .compact_to_new_dir(format!("./data/{}", generate_random_name()))
+            .unwrap();
+    });
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    for span in [
+        "recovery{",
+        "put{key_len=3 bytes=2 file_id=0}",
+        "get{key_len=3 file_id=0 bytes=2}",
+        "delete{key_len=3 file_id=0}",
+        "prepare_compaction{immutable_files=1}",
+        "rotate{file_id=1}",
+        "start_compaction{",
+        "finish_compaction{",
+    ] {
+        assert!(output.contains(span), "no {} span in {}", span, output);
+    }
+}
+
+#[test]
+fn torn_tail_truncation_only_cuts_a_torn_record() {
+    use std::io::{Seek, SeekFrom, Write};
+    let data_dir = format!("./data/{}", generate_random_name());
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    for i in 0..100u8 {
+        bitcask.put(&[i], &[i; 10]).unwrap();
+    }
+    drop(bitcask);
+    let path = format!("{}/0.bitcask", data_dir);
+    let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 100 * 31);
+    let options = Options::default().truncate_torn_tail(true);
+
+    // a garbled value size makes the first record run past the end of the file, but the records after it are intact
+    file.seek(SeekFrom::Start(12 + 5)).unwrap();
+    file.write_all(&[0x01]).unwrap();
+    let error = BitCask::with_options(data_dir.clone(), options.clone()).err().unwrap();
+    assert!(matches!(error, BitCaskError::CorruptedData(_)), "{:?}", error);
+    assert_eq!(file.metadata().unwrap().len(), 100 * 31);
+    file.seek(SeekFrom::Start(12 + 5)).unwrap();
+    file.write_all(&[0]).unwrap();
+
+    // a value cut short is cut off, but only with the option
+    file.set_len(100 * 31 - 5).unwrap();
+    assert!(BitCask::new(data_dir.clone()).is_err());
+    let bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    assert_eq!(bitcask.size(), 99);
+    assert_eq!(bitcask.get(&[98]), Some(vec![98; 10]));
+    assert_eq!(file.metadata().unwrap().len(), 99 * 31);
+    drop(bitcask);
+
+    // a header cut short is not
+    file.seek(SeekFrom::End(0)).unwrap();
+    file.write_all(&[0; 15]).unwrap();
+    let error = BitCask::with_options(data_dir, options).err().unwrap();
+    assert!(matches!(error, BitCaskError::CorruptedData(_)), "{:?}", error);
+    assert_eq!(file.metadata().unwrap().len(), 99 * 31 + 15);
+}
+
+#[test]
+fn event_listeners() {
+    use bitcask_engine_rs::events::{Event, EventDelivery, EventListener};
+    use std::io::Write;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Collector(Mutex<Vec<Event>>);
+    impl EventListener for Collector {
+        fn on_event(&self, event: &Event) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+    impl Collector {
+        fn take(&self) -> Vec<Event> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    let collector = Arc::new(Collector::default());
+    let options = Options::default()
+        .blob_threshold(64)
+        .truncate_torn_tail(true)
+        .event_listener(collector.clone());
+    let mut bitcask =
+        BitCask::with_options(format!("./data/{}", generate_random_name()), options.clone()).unwrap();
+    bitcask.put(&[1], &[1; 10]).unwrap();
+    bitcask.put(&[2], &[2; 100]).unwrap();
+    let data_dir = format!("./data/{}", generate_random_name());
+    bitcask.compact_to_new_dir(data_dir.clone()).unwrap();
+    assert_eq!(
+        collector.take(),
+        vec![
+            Event::FileRotated { file_id: 1 },
+            Event::CompactionStarted { immutable_files: 1 },
+            Event::CompactionFinished {
+                dir: data_dir.clone().into(),
+                live_keys: 2
+            },
+        ]
+    );
+    bitcask.put(&[3], &[3; 10]).unwrap();
+    drop(bitcask);
+
+    // the process stopped in the middle of appending the value of a record: its header, its key and 4 of its 10 bytes
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(format!("{}/1.bitcask", data_dir))
+        .unwrap();
+    file.write_all(&[0xde, 0xad, 0xbe, 0xef]).unwrap();
+    file.write_all(&1u64.to_be_bytes()).unwrap();
+    file.write_all(&10u64.to_be_bytes()).unwrap();
+    file.write_all(&[4, 4, 4, 4, 4]).unwrap();
+    let bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    assert_eq!(
+        collector.take(),
+        vec![Event::RecoveryTruncated {
+            file_id: 1,
+            offset: 31,
+            bytes: 25
+        }]
+    );
+    assert_eq!(bitcask.get(&[3]), Some(vec![3; 10]));
+    drop(bitcask);
+
+    // a lost blob file only shows when its values are read, and `get` reports them as missing
+    std::fs::remove_file(format!("{}/0.blob", data_dir)).unwrap();
+    let bitcask = BitCask::with_options(data_dir.clone(), options).unwrap();
+    assert_eq!(bitcask.get(&[2]), None);
+    assert_eq!(
+        collector.take(),
+        vec![
+            Event::CorruptionDetected {
+                message: "missing blob file 0".to_string()
+            },
+            Event::BackgroundError {
+                message: "Data is corrupted: missing blob file 0".to_string()
+            },
+        ]
+    );
+
+    struct Forwarder(Mutex<Sender<(Option<String>, Event)>>);
+    impl EventListener for Forwarder {
+        fn on_event(&self, event: &Event) {
+            let thread_name = std::thread::current().name().map(str::to_string);
+            self.0.lock().unwrap().send((thread_name, event.clone())).unwrap();
+        }
+    }
+    let (sender, receiver) = channel();
+    let options = Options::default()
+        .event_listener(Arc::new(Forwarder(Mutex::new(sender))))
+        .event_delivery(EventDelivery::Background);
+    let bitcask = BitCask::with_options(format!("./data/{}", generate_random_name()), options).unwrap();
+    bitcask
+        .compact_to_new_dir(format!("./data/{}", generate_random_name()))
+        .unwrap();
+    let (thread_name, event) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(thread_name.as_deref(), Some("bitcask-events"));
+    assert_eq!(event, Event::FileRotated { file_id: 1 });
+}
+
+#[test]
+fn close_and_reopen() {
+    use std::io::{Seek, SeekFrom, Write};
+    let data_dir = format!("./data/{}", generate_random_name());
+    let options = Options::default().lock_directory(true);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    let error = BitCask::with_options(data_dir.clone(), options.clone()).err().unwrap();
+    assert!(matches!(error, BitCaskError::IoError(e) if e.kind() == std::io::ErrorKind::WouldBlock));
+    bitcask.put(&[1], &[1; 10]).unwrap();
+    bitcask.put(&[2], &[2; 10]).unwrap();
+    bitcask.delete(&[2]).unwrap();
+    let mut clone = bitcask.clone();
+    bitcask.close().unwrap();
+    assert!(matches!(clone.put(&[3], &[3]), Err(BitCaskError::Closed)));
+    assert!(matches!(clone.try_get(&[1]), Err(BitCaskError::Closed)));
+    assert!(matches!(bitcask.close(), Err(BitCaskError::Closed)));
+    assert_eq!(clone.get(&[1]), None);
+
+    // the directory lock is released, and the index is loaded from the snapshot rather than from the log, which
+    // is why a damaged value only shows once it is read
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("{}/0.bitcask", data_dir))
+        .unwrap();
+    file.seek(SeekFrom::Start(21)).unwrap();
+    file.write_all(&[0xff]).unwrap();
+    let bitcask = BitCask::with_options(data_dir.clone(), options.clone().verify_checksums(true)).unwrap();
+    assert_eq!(bitcask.tombstone_count(), 1);
+    assert!(matches!(bitcask.try_get(&[1]), Err(BitCaskError::CorruptedData(_))));
+    drop(bitcask);
+    file.seek(SeekFrom::Start(21)).unwrap();
+    file.write_all(&[1]).unwrap();
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 10]));
+    // the log grows past the snapshot, which is then ignored
+    bitcask.put(&[3], &[3]).unwrap();
+    drop(bitcask);
+    let bitcask = BitCask::with_options(data_dir, options).unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 10]));
+    assert_eq!(bitcask.get(&[2]), None);
+    assert_eq!(bitcask.get(&[3]), Some(vec![3]));
+}
+
+#[test]
+fn recover_after_panic() {
+    use bitcask_engine_rs::events::{Event, EventListener};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Panics on the first rotation, while the storage lock is held.
+    struct PanicOnRotation(AtomicBool);
+    impl EventListener for PanicOnRotation {
+        fn on_event(&self, event: &Event) {
+            if matches!(event, Event::FileRotated { .. }) && !self.0.swap(true, Ordering::Relaxed) {
+                panic!("listener panicked");
+            }
+        }
+    }
+
+    let options = Options::default()
+        .group_commit(true)
+        .event_listener(Arc::new(PanicOnRotation(AtomicBool::new(false))));
+    let mut bitcask = BitCask::with_options(format!("./data/{}", generate_random_name()), options).unwrap();
+    bitcask.put(&[1], &[1]).unwrap();
+    let handle = bitcask.clone();
+    let compacted_dir = format!("./data/{}", generate_random_name());
+    std::thread::spawn(move || handle.compact_to_new_dir(compacted_dir))
+        .join()
+        .unwrap_err();
+    assert!(matches!(bitcask.try_get(&[1]), Err(BitCaskError::Poisoned)));
+    assert!(matches!(bitcask.put(&[2], &[2]), Err(BitCaskError::Poisoned)));
+    assert!(matches!(bitcask.stats(), Err(BitCaskError::Poisoned)));
+    assert_eq!(bitcask.size(), 1);
+
+    bitcask.recover().unwrap();
+    assert_eq!(bitcask.get(&[1]), Some(vec![1]));
+    bitcask.put(&[2], &[2]).unwrap();
+    bitcask
+        .compact_to_new_dir(format!("./data/{}", generate_random_name()))
+        .unwrap();
+    assert_eq!(bitcask.get(&[2]), Some(vec![2]));
+}
+
+#[test]
+fn recover_cuts_a_torn_tail_with_default_options() {
+    use std::io::Write;
+    let data_dir = format!("./data/{}", generate_random_name());
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&[1], &[1; 10]).unwrap();
+    // the record a panic stopped in the middle of: its header, its key and 4 of its 10 bytes
+    let path = format!("{}/0.bitcask", data_dir);
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[0xde, 0xad, 0xbe, 0xef]).unwrap();
+    file.write_all(&1u64.to_be_bytes()).unwrap();
+    file.write_all(&10u64.to_be_bytes()).unwrap();
+    file.write_all(&[2, 2, 2, 2, 2]).unwrap();
+    bitcask.recover().unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 31);
+    assert_eq!(bitcask.get(&[1]), Some(vec![1; 10]));
+    assert_eq!(bitcask.get(&[2]), None);
+    bitcask.put(&[2], &[2]).unwrap();
+    drop(bitcask);
+    let bitcask = BitCask::new(data_dir).unwrap();
+    assert_eq!(bitcask.get(&[2]), Some(vec![2]));
+}
+
+#[test]
+fn export_and_import() {
+    let mut bitcask = generate_random_bitcask_instance();
+    bitcask.put(&vec![0, 255], &vec![1, 2, 3]).unwrap();
+    bitcask.put(&vec![1], &vec![4]).unwrap();
+    bitcask.put(&vec![2], &vec![5]).unwrap();
+    bitcask.delete(&vec![2]).unwrap();
+    let format = ExportFormat::JsonLines(Encoding::Base64);
+    let mut dump = Vec::new();
+    assert_eq!(bitcask.export(&mut dump, format).unwrap(), 2);
+    assert_eq!(
+        String::from_utf8(dump.clone()).unwrap(),
+        "{\"key\":\"AP8=\",\"value\":\"AQID\"}\n{\"key\":\"AQ==\",\"value\":\"BA==\"}\n"
+    );
+    // binary data cannot be exported as utf-8
+    bitcask
+        .export(Vec::new(), ExportFormat::JsonLines(Encoding::Utf8))
+        .unwrap_err();
+
+    let imported = generate_random_bitcask_instance();
+    assert_eq!(imported.import(dump.as_slice(), format).unwrap(), 2);
+    assert_eq!(imported.get(&vec![0, 255]), Some(vec![1, 2, 3]));
+    assert_eq!(imported.get(&vec![1]), Some(vec![4]));
+    assert_eq!(imported.get(&vec![2]), None);
+}
+
+#[test]
+fn export_and_import_csv() {
+    let mut bitcask = generate_random_bitcask_instance();
+    bitcask.put(&b"a,b".to_vec(), &b"say \"hi\"\n".to_vec()).unwrap();
+    bitcask.put(&b"c".to_vec(), &b"d".to_vec()).unwrap();
+    let format = ExportFormat::Csv(Encoding::Utf8);
+    let mut dump = Vec::new();
+    bitcask.export(&mut dump, format).unwrap();
+    assert_eq!(
+        String::from_utf8(dump.clone()).unwrap(),
+        "key,value\n\"a,b\",\"say \"\"hi\"\"\n\"\nc,d\n"
+    );
+    let imported = generate_random_bitcask_instance();
+    assert_eq!(imported.import(dump.as_slice(), format).unwrap(), 2);
+    assert_eq!(imported.get(&b"a,b".to_vec()), Some(b"say \"hi\"\n".to_vec()));
+    assert_eq!(imported.get(&b"c".to_vec()), Some(b"d".to_vec()));
 }
 
 fn generate_random_bitcask_instance() -> BitCask {
@@ -50,7 +989,7 @@ fn generate_random_bitcask_instance() -> BitCask {
 }
 
 fn generate_random_name() -> String {
-    let mut rng = rand::thread_rng();
+    let rng = rand::thread_rng();
     let rand_string: String = rng
         .sample_iter(rand::distributions::Alphanumeric)
         .take(10)
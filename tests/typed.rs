@@ -0,0 +1,115 @@
+use bitcask_engine_rs::bitcask::{BitCask, PutOption};
+use bitcask_engine_rs::typed::{JsonCodec, OrderedKey, TypedBitCask};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn typed_put_get() {
+    let mut users: TypedBitCask<(String, i64), User> =
+        TypedBitCask::new(generate_random_bitcask_instance());
+    let alice = User {
+        name: "alice".to_string(),
+        age: 30,
+    };
+    users.put(&("eu".to_string(), -1), &alice).unwrap();
+    assert_eq!(
+        users.get(&("eu".to_string(), -1)).unwrap(),
+        Some(alice.clone())
+    );
+    assert_eq!(users.get(&("eu".to_string(), 1)).unwrap(), None);
+    users
+        .put_with_option(&("eu".to_string(), -1), &alice, PutOption::nx())
+        .unwrap_err();
+    users.delete(&("eu".to_string(), -1)).unwrap();
+    assert_eq!(users.get(&("eu".to_string(), -1)).unwrap(), None);
+}
+
+#[test]
+fn typed_range_is_ordered() {
+    let mut numbers: TypedBitCask<i32, String, JsonCodec> =
+        TypedBitCask::new(generate_random_bitcask_instance());
+    for i in [300, -2, 7, -300, 0, 256] {
+        numbers.put(&i, &i.to_string()).unwrap();
+    }
+    let keys: Vec<i32> = numbers
+        .range(-2..=256)
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, vec![-2, 0, 7, 256]);
+    let all: Vec<i32> = numbers
+        .range(..)
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(all, vec![-300, -2, 0, 7, 256, 300]);
+    let inverted = numbers
+        .range((Bound::Included(256), Bound::Included(-2)))
+        .unwrap();
+    assert!(inverted.is_empty());
+}
+
+#[test]
+fn ordered_key_encoding() {
+    let keys = [
+        ("".to_string(), 0u8),
+        ("a".to_string(), 1),
+        ("a\0".to_string(), 0),
+        ("ab".to_string(), 0),
+        ("b".to_string(), 0),
+    ];
+    let encoded: Vec<Vec<u8>> = keys.iter().map(|key| key.encode_key()).collect();
+    let mut sorted = encoded.clone();
+    sorted.sort();
+    assert_eq!(encoded, sorted);
+    for (key, encoded) in keys.iter().zip(encoded) {
+        assert_eq!(&<(String, u8)>::decode_key(&encoded).unwrap(), key);
+    }
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn typed_bincode_codec() {
+    use bitcask_engine_rs::typed::BincodeCodec;
+    let mut users: TypedBitCask<u64, User, BincodeCodec> =
+        TypedBitCask::new(generate_random_bitcask_instance());
+    let bob = User {
+        name: "bob".to_string(),
+        age: 40,
+    };
+    users.put(&1, &bob).unwrap();
+    assert_eq!(users.get(&1).unwrap(), Some(bob));
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn typed_msgpack_codec() {
+    use bitcask_engine_rs::typed::MsgPackCodec;
+    let mut users: TypedBitCask<u64, User, MsgPackCodec> =
+        TypedBitCask::new(generate_random_bitcask_instance());
+    let bob = User {
+        name: "bob".to_string(),
+        age: 40,
+    };
+    users.put(&1, &bob).unwrap();
+    assert_eq!(users.get(&1).unwrap(), Some(bob));
+}
+
+fn generate_random_bitcask_instance() -> BitCask {
+    let rng = rand::thread_rng();
+    let rand_string: String = rng
+        .sample_iter(rand::distributions::Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    BitCask::new(format!("./data/{}", rand_string)).unwrap()
+}
@@ -0,0 +1,269 @@
+// the tests written against the `&Vec<u8>` API still pass vectors, which the slice API accepts as they are
+#![allow(clippy::useless_vec)]
+
+use bitcask_engine_rs::bitcask::{BitCask, KVStorage, Options};
+use bitcask_engine_rs::repair::{repair, LostRange};
+use bitcask_engine_rs::verify::{verify, Problem};
+use rand::Rng;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+
+#[test]
+fn verify_clean_directory() {
+    let data_dir = generate_random_data_dir();
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&vec![1, 2, 3], &vec![4, 5, 6]).unwrap();
+    bitcask.put(&vec![1, 2], &vec![3, 4]).unwrap();
+    bitcask.delete(&vec![1, 2]).unwrap();
+    let report = verify(&data_dir).unwrap();
+    assert!(report.ok, "{}", report.to_json());
+    assert_eq!(report.files.len(), 1);
+    assert_eq!(
+        (report.files[0].records, report.files[0].tombstones),
+        (3, 1)
+    );
+    assert_eq!(report.live_keys, 1);
+}
+
+#[test]
+fn verify_detects_corruption() {
+    let data_dir = generate_random_data_dir();
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&vec![1, 2, 3], &vec![4, 5, 6]).unwrap();
+    bitcask.put(&vec![1, 2], &vec![3, 4]).unwrap();
+    let file = OpenOptions::new()
+        .write(true)
+        .open(format!("{}/0.bitcask", data_dir))
+        .unwrap();
+    // flip the first byte of the first value
+    write_at(&file, 23, &[0xff]);
+    let report = verify(&data_dir).unwrap();
+    assert!(!report.ok);
+    assert_eq!(
+        report.problems,
+        vec![Problem::ChecksumMismatch {
+            file_id: 0,
+            offset: 0
+        }]
+    );
+
+    // chop off the last byte of the second record
+    write_at(&file, 23, &[4]);
+    file.set_len(26 + 23).unwrap();
+    let report = verify(&data_dir).unwrap();
+    assert_eq!(
+        report.problems,
+        vec![Problem::TruncatedTail {
+            file_id: 0,
+            offset: 26,
+            trailing_bytes: 23
+        }]
+    );
+    assert!(report.to_json().contains("\"kind\": \"truncated_tail\""));
+}
+
+#[test]
+fn verify_detects_bad_headers() {
+    let data_dir = generate_random_data_dir();
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&[1], &[1, 1]).unwrap();
+    bitcask.put(&[2], &[2, 2]).unwrap();
+    let file = OpenOptions::new()
+        .write(true)
+        .open(format!("{}/0.bitcask", data_dir))
+        .unwrap();
+    // set an unknown flag on the second record, every record is 23 bytes long
+    write_at(&file, 23 + 4, &[0x80]);
+    let report = verify(&data_dir).unwrap();
+    assert_eq!(
+        report.problems,
+        vec![Problem::UnknownFlags {
+            file_id: 0,
+            offset: 23,
+            flags: 0x80
+        }]
+    );
+    assert_eq!(report.files[0].valid_bytes, 23);
+
+    // a value size way beyond a log file
+    write_at(&file, 23 + 4, &[0]);
+    write_at(&file, 23 + 12, &(1u64 << 40).to_be_bytes());
+    let report = verify(&data_dir).unwrap();
+    assert_eq!(
+        report.problems,
+        vec![Problem::InvalidRecordSize {
+            file_id: 0,
+            offset: 23,
+            key_size: 1,
+            value_size: 1 << 40
+        }]
+    );
+}
+
+#[test]
+fn verify_detects_bad_file_names() {
+    let data_dir = generate_random_data_dir();
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&[1], &[1, 1]).unwrap();
+    drop(bitcask);
+    let log_file = format!("{}/0.bitcask", data_dir);
+    std::fs::copy(&log_file, format!("{}/00.bitcask", data_dir)).unwrap();
+    std::fs::copy(&log_file, format!("{}/1.bitcask", data_dir)).unwrap();
+    std::fs::copy(&log_file, format!("{}/3.bitcask", data_dir)).unwrap();
+    std::fs::copy(&log_file, format!("{}/old.bitcask", data_dir)).unwrap();
+    let report = verify(&data_dir).unwrap();
+    assert!(!report.ok);
+    assert_eq!(
+        report.problems,
+        vec![
+            Problem::UnreadableFile {
+                path: format!("{}/old.bitcask", data_dir),
+                error: "file name is not a file id".to_string()
+            },
+            Problem::MissingFileId { file_id: 2 },
+            Problem::DuplicateFileId {
+                file_id: 0,
+                paths: vec![format!("{}/0.bitcask", data_dir), format!("{}/00.bitcask", data_dir)]
+            },
+        ]
+    );
+
+    // a directory that does not start with file 0
+    let data_dir = generate_random_data_dir();
+    std::fs::create_dir_all(&data_dir).unwrap();
+    std::fs::copy(&log_file, format!("{}/1.bitcask", data_dir)).unwrap();
+    let report = verify(&data_dir).unwrap();
+    assert_eq!(report.problems, vec![Problem::MissingFileId { file_id: 0 }]);
+}
+
+#[test]
+fn verify_after_repeated_compaction() {
+    let mut bitcask = BitCask::new(generate_random_data_dir()).unwrap();
+    bitcask.put(&vec![1], &vec![1]).unwrap();
+    bitcask
+        .compact_to_new_dir(generate_random_data_dir())
+        .unwrap();
+    bitcask.put(&vec![2], &vec![2]).unwrap();
+    let data_dir = generate_random_data_dir();
+    bitcask.compact_to_new_dir(data_dir.clone()).unwrap();
+    bitcask.put(&vec![3], &vec![3]).unwrap();
+    // the compacted file 0 is followed by file 2, which is not a missing file
+    assert_eq!(bitcask.get(&vec![1]), Some(vec![1]));
+    assert_eq!(bitcask.get(&vec![3]), Some(vec![3]));
+    let report = verify(&data_dir).unwrap();
+    assert!(report.ok, "{}", report.to_json());
+}
+
+#[test]
+fn repair_salvages_records_after_corruption() {
+    let data_dir = generate_random_data_dir();
+    let mut bitcask = BitCask::new(data_dir.clone()).unwrap();
+    bitcask.put(&vec![1], &vec![1, 1]).unwrap();
+    bitcask.put(&vec![2], &vec![2, 2]).unwrap();
+    bitcask.put(&vec![3], &vec![3, 3]).unwrap();
+    bitcask.delete(&vec![1]).unwrap();
+    // garble the header of the second record, every value record is 23 bytes long
+    let file = OpenOptions::new()
+        .write(true)
+        .open(format!("{}/0.bitcask", data_dir))
+        .unwrap();
+    write_at(&file, 23 + 4, &[0xff; 6]);
+    BitCask::new(data_dir.clone()).err().unwrap();
+
+    let dest_dir = generate_random_data_dir();
+    let report = repair(&data_dir, &dest_dir).unwrap();
+    assert_eq!(report.files[0].records, 3);
+    assert_eq!(
+        report.files[0].lost_ranges,
+        vec![LostRange { start: 23, end: 46 }]
+    );
+    assert_eq!(report.lost_bytes, 23);
+    let repaired = BitCask::new(dest_dir.clone()).unwrap();
+    assert_eq!(repaired.get(&vec![1]), None);
+    assert_eq!(repaired.get(&vec![2]), None);
+    assert_eq!(repaired.get(&vec![3]), Some(vec![3, 3]));
+    assert!(verify(&dest_dir).unwrap().ok);
+}
+
+#[test]
+fn verify_detects_dangling_blob_pointers() {
+    let data_dir = generate_random_data_dir();
+    let mut bitcask = BitCask::with_options(data_dir.clone(), Options::default().blob_threshold(16)).unwrap();
+    bitcask.put(&vec![1], &vec![1; 100]).unwrap();
+    bitcask.put(&vec![2], &vec![2; 100]).unwrap();
+    bitcask.put(&vec![3], &vec![3; 100]).unwrap();
+    // a blob that is no longer live is not checked
+    bitcask.put(&vec![3], &vec![3]).unwrap();
+    drop(bitcask);
+    assert!(verify(&data_dir).unwrap().ok);
+
+    // every blob record is 20 bytes of header, 1 byte of key and 28 bytes of blob pointer
+    let blob_path = format!("{}/0.blob", data_dir);
+    let blob_file = OpenOptions::new().write(true).open(&blob_path).unwrap();
+    blob_file.set_len(150).unwrap();
+    let report = verify(&data_dir).unwrap();
+    assert!(!report.ok);
+    assert_eq!(
+        report.problems,
+        vec![Problem::BlobPastEof {
+            file_id: 0,
+            offset: 49,
+            blob_file_id: 0,
+            blob_end: 200,
+            blob_file_size: 150
+        }]
+    );
+
+    std::fs::remove_file(&blob_path).unwrap();
+    assert_eq!(
+        verify(&data_dir).unwrap().problems,
+        vec![
+            Problem::MissingBlobFile {
+                file_id: 0,
+                offset: 0,
+                blob_file_id: 0
+            },
+            Problem::MissingBlobFile {
+                file_id: 0,
+                offset: 49,
+                blob_file_id: 0
+            },
+        ]
+    );
+}
+
+#[test]
+fn repair_keeps_blob_files() {
+    let data_dir = generate_random_data_dir();
+    let options = Options::default().blob_threshold(16);
+    let mut bitcask = BitCask::with_options(data_dir.clone(), options.clone()).unwrap();
+    bitcask.put(&vec![1], &vec![1; 100]).unwrap();
+    bitcask.put(&vec![2], &vec![2]).unwrap();
+    drop(bitcask);
+
+    let dest_dir = generate_random_data_dir();
+    let report = repair(&data_dir, &dest_dir).unwrap();
+    assert_eq!(report.lost_bytes, 0);
+    assert_eq!(report.blob_files, vec![format!("{}/0.blob", data_dir)]);
+    assert!(verify(&dest_dir).unwrap().ok);
+    let repaired = BitCask::with_options(dest_dir.clone(), options).unwrap();
+    assert_eq!(repaired.try_get(&vec![1]).unwrap(), Some(vec![1; 100]));
+    assert_eq!(repaired.try_get(&vec![2]).unwrap(), Some(vec![2]));
+    // repairing into the same destination again would mix the blob files up
+    assert!(repair(&data_dir, &dest_dir).is_err());
+}
+
+fn write_at(mut file: &File, offset: u64, bytes: &[u8]) {
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+fn generate_random_data_dir() -> String {
+    let rng = rand::thread_rng();
+    let rand_string: String = rng
+        .sample_iter(rand::distributions::Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    format!("./data/{}", rand_string)
+}